@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::converter::{ChiselConverter, SpinalConverter, write_to_file};
+use crate::hdl_info::{HdlInfo, Module};
+
+/// Code generator for one target. A backend turns each module into one file
+/// and may add files shared by all modules, such as constants.
+pub trait Backend {
+    /// Name the backend is selected by on the command line.
+    fn name(&self) -> &str;
+
+    fn file_extension(&self) -> &str;
+
+    /// Directory below the output directory the files are written to.
+    fn directory(&self) -> PathBuf {
+        PathBuf::from(self.name())
+    }
+
+    /// File name of `module`, without extension.
+    fn file_name(&self, module: &Module) -> String {
+        module.get_name()
+    }
+
+    /// Code of `module`, with the whole design for cross-module context.
+    fn emit_module(&self, module: &Module, hdl_info: &HdlInfo) -> Result<String, Box<dyn Error>>;
+
+    /// Files shared by all modules, as (file name without extension, contents).
+    fn emit_shared(&self, _hdl_info: &HdlInfo) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Issues to report about the design, such as names the backend changed.
+    fn warnings(&self, _hdl_info: &HdlInfo) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Write every module and the shared files below `output`.
+    fn emit(&self, output: &Path, hdl_info: &HdlInfo) -> Result<(), Box<dyn Error>> {
+        emit_files(self, output, hdl_info)
+    }
+}
+
+/// One file per module and per shared file, for backends overriding `emit`
+/// to fall back on.
+pub fn emit_files<B: Backend + ?Sized>(backend: &B, output: &Path, hdl_info: &HdlInfo) -> Result<(), Box<dyn Error>> {
+    let path = output.join(backend.directory());
+    for module in hdl_info.get_modules() {
+        write_to_file(&path, &backend.file_name(module), &backend.emit_module(module, hdl_info)?, backend.file_extension());
+    }
+    backend.emit_shared(hdl_info).iter().for_each(|(name, contents)| {
+        write_to_file(&path, name, contents, backend.file_extension());
+    });
+    Ok(())
+}
+
+/// Backends available by name, in registration order.
+#[derive(Default)]
+pub struct BackendRegistry {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl BackendRegistry {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    /// Chisel and SpinalHDL BlackBoxes, each with an inline and a separate
+    /// bundle variant, and Chisel ExtModules, with the options set on
+    /// `chisel` and `spinal`.
+    pub fn with_converters(chisel: ChiselConverter, spinal: SpinalConverter) -> Self {
+        Self::builder()
+            .register(chisel.clone())
+            .register(chisel.clone().split_bundle())
+            .register(chisel.ext_module())
+            .register(spinal.clone())
+            .register(spinal.split_bundle())
+    }
+
+    /// Add `backend`, replacing a registered backend of the same name.
+    pub fn register(mut self, backend: impl Backend + 'static) -> Self {
+        self.backends.retain(|b| b.name() != backend.name());
+        self.backends.push(Box::new(backend));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Backend> {
+        self.backends.iter().find(|b| b.name() == name).map(|b| b.as_ref())
+    }
+
+    pub fn get_backends(&self) -> &Vec<Box<dyn Backend>> {
+        &self.backends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_registered_backends() {
+        let registry = BackendRegistry::with_converters(ChiselConverter::builder(), SpinalConverter::builder());
+        let names = registry.get_backends().iter().map(|b| b.name()).collect::<Vec<&str>>();
+        assert_eq!(names, ["chisel", "chisel-split-bundle", "chisel-ext-module", "spinal", "spinal-split-bundle"]);
+
+        // 分离 bundle 的变体输出到原有的子目录
+        let backend = registry.get("spinal-split-bundle").unwrap();
+        assert_eq!(backend.directory(), PathBuf::from("spinal").join("split_bundle"));
+        assert_eq!(backend.file_extension(), "scala");
+        assert!(registry.get("verilog").is_none());
+
+        // 同名注册替换原有后端
+        let registry = BackendRegistry::builder()
+            .register(ChiselConverter::builder())
+            .register(ChiselConverter::builder());
+        assert_eq!(registry.get_backends().len(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::hdl_info::{Module, Port};
+
+/// Clock input of a module with the reset of the registers it drives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockDomain {
+    clock: String,
+    reset: Option<String>,
+    reset_active_low: bool,
+}
+
+impl ClockDomain {
+    pub fn get_clock(&self) -> String {
+        self.clock.clone()
+    }
+
+    pub fn get_reset(&self) -> Option<String> {
+        self.reset.clone()
+    }
+
+    pub fn is_reset_active_low(&self) -> bool {
+        self.reset_active_low
+    }
+}
+
+const CLOCK_TOKENS: [&str; 2] = ["clk", "clock"];
+const RESET_TOKENS: [&str; 8] = ["rst", "rstn", "reset", "resetn", "arst", "arstn", "srst", "srstn"];
+/// Tokens of control signals named after a clock or reset, like `clk_en`.
+const CONTROL_TOKENS: [&str; 12] = ["en", "ena", "enable", "gate", "sel", "div", "cnt", "count", "done", "req", "ack", "sync"];
+/// Last tokens of active low names, like `rst_n` or lowRISC `rst_ni`.
+const ACTIVE_LOW_TOKENS: [&str; 5] = ["n", "ni", "b", "l", "bi"];
+const PORT_SUFFIX_TOKENS: [&str; 3] = ["i", "in", "ni"];
+
+fn tokens(name: &str) -> Vec<String> {
+    name.trim_start_matches('\\').to_ascii_lowercase().split('_').map(str::to_string).collect()
+}
+
+fn is_control(tokens: &[String]) -> bool {
+    tokens.iter().any(|t| CONTROL_TOKENS.contains(&t.as_str()))
+}
+
+/// `clk`, `clk_i`, `sys_clk`, `clk2x`, but not `clk_en`.
+fn is_clock_name(name: &str) -> bool {
+    let tokens = tokens(name);
+    !is_control(&tokens) && tokens.iter().any(|t| {
+        CLOCK_TOKENS.iter().any(|c| t.strip_prefix(c).is_some_and(|rest| rest.chars().all(|c| c.is_ascii_digit() || c == 'x')))
+    })
+}
+
+/// `rst`, `rst_n`, `resetn`, `arst_ni`, but not `rst_done`.
+fn is_reset_name(name: &str) -> bool {
+    let tokens = tokens(name);
+    !is_control(&tokens) && tokens.iter().any(|t| RESET_TOKENS.contains(&t.as_str()))
+}
+
+fn is_active_low_name(name: &str) -> bool {
+    let tokens = tokens(name);
+    tokens.last().is_some_and(|t| ACTIVE_LOW_TOKENS.contains(&t.as_str()) || t.ends_with("rstn") || t.ends_with("resetn"))
+}
+
+/// What is left of a clock or reset name to pair them: `clk_a` and
+/// `rst_a_n` both give `a`.
+fn domain_key(name: &str) -> Vec<String> {
+    tokens(name).into_iter()
+        .filter(|t| !CLOCK_TOKENS.iter().any(|c| t.starts_with(c)) && !RESET_TOKENS.contains(&t.as_str()))
+        .filter(|t| !ACTIVE_LOW_TOKENS.contains(&t.as_str()) && !PORT_SUFFIX_TOKENS.contains(&t.as_str()))
+        .collect()
+}
+
+fn is_single_bit_input(port: &Port) -> bool {
+    port.get_direction() == "input" && port.get_width() == "1"
+}
+
+/// Clock domains of `module`, in port order. Clocks and asynchronous resets
+/// come from the `posedge`/`negedge` events of `always` blocks, where a
+/// `negedge` reset is active low. Ports named like clocks add domains, and
+/// ports named like resets go to the clock whose name they share, or to the
+/// only clock. Only single bit inputs are taken.
+pub fn clock_domains(module: &Module) -> Vec<ClockDomain> {
+    let inputs = module.get_ports().iter()
+        .filter(|p| is_single_bit_input(p))
+        .map(|p| p.get_name())
+        .collect::<Vec<String>>();
+    let mut domains: Vec<ClockDomain> = Vec::new();
+
+    for events in module.get_edge_events() {
+        let events = events.iter().filter(|e| inputs.contains(&e.get_signal())).collect::<Vec<_>>();
+        let clock = events.iter().find(|e| is_clock_name(&e.get_signal()))
+            .or_else(|| events.iter().find(|e| !is_reset_name(&e.get_signal())));
+        let Some(clock) = clock else {
+            continue;
+        };
+        let reset = events.iter().find(|e| e.get_signal() != clock.get_signal());
+        match domains.iter_mut().find(|d| d.clock == clock.get_signal()) {
+            Some(domain) if domain.reset.is_some() => {}
+            Some(domain) => {
+                domain.reset = reset.map(|r| r.get_signal());
+                domain.reset_active_low = reset.is_some_and(|r| r.get_edge() == "negedge");
+            }
+            None => domains.push(ClockDomain {
+                clock: clock.get_signal(),
+                reset: reset.map(|r| r.get_signal()),
+                reset_active_low: reset.is_some_and(|r| r.get_edge() == "negedge"),
+            }),
+        }
+    }
+
+    for name in inputs.iter().filter(|n| is_clock_name(n)) {
+        let used = domains.iter().any(|d| &d.clock == name || d.reset.as_ref() == Some(name));
+        if !used {
+            domains.push(ClockDomain { clock: name.clone(), reset: None, reset_active_low: false });
+        }
+    }
+
+    let single_domain = domains.len() == 1;
+    for name in inputs.iter().filter(|n| is_reset_name(n) && !is_clock_name(n)) {
+        if domains.iter().any(|d| d.reset.as_ref() == Some(name)) {
+            continue;
+        }
+        let domain = domains.iter_mut()
+            .filter(|d| d.reset.is_none())
+            .find(|d| single_domain || domain_key(&d.clock) == domain_key(name));
+        if let Some(domain) = domain {
+            domain.reset = Some(name.clone());
+            domain.reset_active_low = is_active_low_name(name);
+        }
+    }
+
+    domains.sort_by_key(|d| inputs.iter().position(|n| n == &d.clock));
+    domains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, path::PathBuf};
+    use crate::sv_parse::parse_file;
+
+    #[test]
+    fn detect_clock_domains() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("clock_domain_module.sv");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let domains = |name: &str| {
+            clock_domains(hdl_info.get_module(name).unwrap()).iter()
+                .map(|d| (d.get_clock(), d.get_reset(), d.is_reset_active_low()))
+                .collect::<Vec<(String, Option<String>, bool)>>()
+        };
+
+        // 敏感列表中的 negedge 复位为低有效
+        assert_eq!(domains("async_reset_module"), [("clk".to_string(), Some("rst_n".to_string()), true)]);
+        // 只有端口名时按名字配对，clk_en 不是时钟
+        assert_eq!(domains("dual_clock_stub"), [
+            ("clk_a_i".to_string(), Some("rst_a_ni".to_string()), true),
+            ("clk_io".to_string(), Some("rst_io".to_string()), false),
+        ]);
+        // 没有时钟的模块没有时钟域
+        assert!(domains("comb_module").is_empty());
+    }
+}
@@ -0,0 +1,1436 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use lazy_static::lazy_static;
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::{FromPrimitive, One, ToPrimitive, Zero};
+use regex::Regex;
+use sv_parser::{
+    CastingType, ConstantExpression, ConstantPartSelectRange, ConstantPrimary, ConstantSelect,
+    DataType, DataTypeOrImplicit, DataTypeOrVoid, EnumBaseType, Expression, ExpressionOrCondPattern,
+    ImplicitDataType, IntegerAtomType, ListOfArguments, Locate, MintypmaxExpression, NonIntegerType,
+    PackedDimension, PartSelectRange, Primary, PrimaryLiteral, RefNode, Select, Signing, SimpleType,
+    StructUnion, SubroutineCall, SyntaxTree, SystemTfCall, TypeDeclarationDataType, UnpackedDimension,
+    VariableDeclAssignment, VariableDimension, parse_sv_str, unwrap_node,
+};
+
+/// Widest value the evaluator can represent, the minimum the LRM requires
+/// implementations to support.
+const MAX_WIDTH: u32 = 1 << 16;
+
+/// Width of unsized literals and `integer`/`int` values.
+const INTEGER_WIDTH: u32 = 32;
+
+/// Sized 4-state SV value. `bits` and `xz` are masked to `width`; bits set in
+/// `xz` are unknown (`x` or `z`) and their `bits` are zero. Real values keep
+/// their value in `real` instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvValue {
+    bits: BigUint,
+    xz: BigUint,
+    width: u32,
+    signed: bool,
+    real: Option<f64>,
+}
+
+impl SvValue {
+    fn new(bits: BigUint, xz: BigUint, width: u32, signed: bool) -> Self {
+        let mask = width_mask(width);
+        let xz = xz & &mask;
+        let bits = bits & &mask;
+        let bits = &bits ^ (&bits & &xz);
+        SvValue { bits, xz, width, signed, real: None }
+    }
+
+    fn from_real(value: f64) -> Self {
+        SvValue { real: Some(value), ..SvValue::new(BigUint::zero(), BigUint::zero(), 64, true) }
+    }
+
+    /// Value of a two's complement integer truncated to `width`.
+    fn from_bigint(value: &BigInt, width: u32, signed: bool) -> Self {
+        let modulus = BigInt::one() << width;
+        let mut value = value % &modulus;
+        if value.sign() == Sign::Minus {
+            value += modulus;
+        }
+        SvValue::new(value.to_biguint().unwrap_or_default(), BigUint::zero(), width, signed)
+    }
+
+    /// Unsized integer literal, 32-bit signed like `integer`.
+    pub fn from_i64(value: i64) -> Self {
+        SvValue::from_bigint(&BigInt::from(value), INTEGER_WIDTH, true)
+    }
+
+    fn from_bool(value: bool) -> Self {
+        SvValue::new(BigUint::from(value as u8), BigUint::zero(), 1, false)
+    }
+
+    fn unknown(width: u32, signed: bool) -> Self {
+        SvValue::new(BigUint::zero(), width_mask(width), width, signed)
+    }
+
+    fn is_known(&self) -> bool {
+        self.xz.is_zero()
+    }
+
+    /// Bits known to be zero.
+    fn zeros(&self) -> BigUint {
+        width_mask(self.width) ^ (&self.bits | &self.xz)
+    }
+
+    /// Value as a two's complement integer, sign extended when signed.
+    fn as_bigint(&self) -> BigInt {
+        let value = BigInt::from(self.bits.clone());
+        if self.signed && self.bits.bit(self.width as u64 - 1) {
+            value - (BigInt::one() << self.width)
+        } else {
+            value
+        }
+    }
+
+    /// Integer value, `None` when any bit is `x` or `z`. Reals are rounded
+    /// away from zero like on assignment to an integral variable.
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match self.real {
+            Some(v) => BigInt::from_f64(v.round()),
+            None => self.is_known().then(|| self.as_bigint()),
+        }
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        match self.real {
+            Some(v) => Some(v),
+            None => self.to_bigint()?.to_f64(),
+        }
+    }
+
+    /// Integral value of a real, as a 64-bit signed integer.
+    fn integral(self) -> Option<Self> {
+        match self.real {
+            Some(_) => Some(SvValue::from_bigint(&self.to_bigint()?, 64, true)),
+            None => Some(self),
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        self.to_bigint()?.to_i64()
+    }
+
+    /// Truth value of a condition: `None` when it depends on unknown bits.
+    fn truth(&self) -> Option<bool> {
+        if let Some(v) = self.real {
+            Some(v != 0.0)
+        } else if !self.bits.is_zero() {
+            Some(true)
+        } else if self.is_known() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Extend or truncate to `width`, sign extending signed values.
+    fn resize(&self, width: u32) -> Self {
+        if width > self.width && self.signed {
+            let top = self.width as u64 - 1;
+            let ext = width_mask(width) ^ width_mask(self.width);
+            let bits = if self.bits.bit(top) { &self.bits | &ext } else { self.bits.clone() };
+            let xz = if self.xz.bit(top) { &self.xz | &ext } else { self.xz.clone() };
+            SvValue::new(bits, xz, width, self.signed)
+        } else {
+            SvValue::new(self.bits.clone(), self.xz.clone(), width, self.signed)
+        }
+    }
+
+    fn with_signed(&self, signed: bool) -> Self {
+        SvValue { signed, ..self.clone() }
+    }
+}
+
+/// Known values print in decimal, values with `x`/`z` bits as a sized
+/// literal such as `8'hx5` or `4'b10x1`, and reals like `2.5`.
+impl std::fmt::Display for SvValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(v) = self.real {
+            return write!(f, "{:?}", v);
+        }
+        if let Some(value) = self.to_bigint() {
+            return write!(f, "{}", value);
+        }
+
+        let sign = if self.signed { "s" } else { "" };
+        let digit = |bits: u32, i: u32| {
+            let range = i * bits..((i + 1) * bits).min(self.width);
+            if range.clone().all(|b| self.xz.bit(b as u64)) {
+                Some('x')
+            } else if range.clone().any(|b| self.xz.bit(b as u64)) {
+                None
+            } else {
+                let v = range.clone().rev().fold(0, |v, b| v << 1 | self.bits.bit(b as u64) as u32);
+                char::from_digit(v, 16)
+            }
+        };
+        let hex = (0..self.width.div_ceil(4)).rev().map(|i| digit(4, i)).collect::<Option<String>>();
+        match hex {
+            Some(hex) => write!(f, "{}'{}h{}", self.width, sign, hex),
+            None => {
+                let bin: String = (0..self.width).rev().map(|i| digit(1, i).unwrap_or('x')).collect();
+                write!(f, "{}'{}b{}", self.width, sign, bin)
+            }
+        }
+    }
+}
+
+/// Typedefs by name, including `pkg::name` for package members. Their
+/// layout is resolved against the evaluation scope when `$bits` and the
+/// array query functions are applied to them.
+pub type TypeMap<'a> = HashMap<String, &'a TypeDeclarationDataType>;
+
+/// Dimensions of a type, outermost first, and the width of one element.
+struct Layout {
+    dimensions: Vec<(BigInt, BigInt)>,
+    element_width: BigUint,
+    signed: bool,
+}
+
+impl Layout {
+    fn scalar(element_width: u64, signed: bool) -> Self {
+        Layout { dimensions: Vec::new(), element_width: BigUint::from(element_width), signed }
+    }
+
+    fn width(&self) -> BigUint {
+        self.dimensions.iter().fold(self.element_width.clone(), |width, (left, right)| {
+            width * ((left - right).magnitude() + 1u32)
+        })
+    }
+
+    /// Bounds of dimension `n` counted from 1; a multi-bit element such as
+    /// `int` or a packed struct adds an implicit `[w-1:0]` innermost.
+    fn dimension(&self, n: usize) -> Option<(BigInt, BigInt)> {
+        if n == self.dimensions.len() + 1 && self.element_width > BigUint::one() {
+            Some((BigInt::from(self.element_width.clone()) - 1, BigInt::zero()))
+        } else {
+            self.dimensions.get(n.checked_sub(1)?).cloned()
+        }
+    }
+}
+
+/// Argument of a system function: a data type or an expression, which may
+/// itself name a typedef.
+enum SystemArg<'b> {
+    Type(&'b DataType),
+    Expression(&'b Expression),
+}
+
+fn width_mask(width: u32) -> BigUint {
+    (BigUint::one() << width) - 1u32
+}
+
+fn clog2(n: &BigInt) -> u64 {
+    if n <= &BigInt::one() {
+        0
+    } else {
+        (n - BigInt::one()).bits()
+    }
+}
+
+/// Integer `**` of known operands. With a negative exponent the result is 1
+/// for a base of 1, 1 or -1 by the parity of the exponent for -1, x for 0 and
+/// 0 otherwise.
+fn pow(base: &SvValue, exp: &SvValue) -> SvValue {
+    let exp = exp.as_bigint();
+    let modulus = BigUint::one() << base.width;
+    let bits = match exp.to_biguint() {
+        Some(exp) => base.bits.modpow(&exp, &modulus),
+        None => match base.as_bigint() {
+            b if b.is_zero() => return SvValue::unknown(base.width, base.signed),
+            b if b.is_one() => BigUint::one(),
+            b if b == -BigInt::one() && (&exp % 2u32).is_zero() => BigUint::one(),
+            b if b == -BigInt::one() => modulus - 1u32,
+            _ => BigUint::zero(),
+        },
+    };
+    SvValue::new(bits, BigUint::zero(), base.width, base.signed)
+}
+
+/// Binding power of binary operators, from `**` down to `->`/`<->`.
+pub(crate) fn binary_precedence(op: &str) -> Option<(u8, bool)> {
+    let prec = match op {
+        "**" => (12, false),
+        "*" | "/" | "%" => (11, false),
+        "+" | "-" => (10, false),
+        "<<" | ">>" | "<<<" | ">>>" => (9, false),
+        "<" | "<=" | ">" | ">=" => (8, false),
+        "==" | "!=" | "===" | "!==" | "==?" | "!=?" => (7, false),
+        "&" => (6, false),
+        "^" | "^~" | "~^" => (5, false),
+        "|" => (4, false),
+        "&&" => (3, false),
+        "||" => (2, false),
+        "->" | "<->" => (0, true),
+        _ => return None,
+    };
+    Some(prec)
+}
+
+/// Binding power of `?:`, between `||` and the implications.
+pub(crate) const TERNARY_PRECEDENCE: u8 = 1;
+
+fn apply_unary(op: &str, a: SvValue) -> Option<SvValue> {
+    if let Some(v) = a.real {
+        return match op {
+            "+" => Some(a),
+            "-" => Some(SvValue::from_real(-v)),
+            "!" => Some(SvValue::from_bool(v == 0.0)),
+            _ => None,
+        };
+    }
+    let known = a.is_known();
+    let reduce = |v: Option<bool>| v.map_or(SvValue::unknown(1, false), SvValue::from_bool);
+    let result = match op {
+        "+" => a,
+        "-" if known => SvValue::from_bigint(&-a.as_bigint(), a.width, a.signed),
+        "-" => SvValue::unknown(a.width, a.signed),
+        "~" => SvValue::new(width_mask(a.width) ^ &a.bits, a.xz.clone(), a.width, a.signed),
+        "!" => reduce(a.truth().map(|v| !v)),
+        "&" | "~&" => {
+            let v = if !a.zeros().is_zero() { Some(false) } else if known { Some(true) } else { None };
+            reduce(v.map(|v| v != (op == "~&")))
+        }
+        "|" | "~|" => reduce(a.truth().map(|v| v != (op == "~|"))),
+        "^" | "~^" | "^~" => {
+            let v = known.then(|| a.bits.count_ones() % 2 == 1);
+            reduce(v.map(|v| v == (op == "^")))
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+pub fn apply_binary(op: &str, a: Option<SvValue>, b: Option<SvValue>) -> Option<SvValue> {
+    // Logical operators short-circuit on a known left operand
+    match (op, a.as_ref().and_then(SvValue::truth)) {
+        ("&&", Some(false)) => return Some(SvValue::from_bool(false)),
+        ("||", Some(true)) => return Some(SvValue::from_bool(true)),
+        _ => (),
+    }
+    let (a, b) = (a?, b?);
+    if a.real.is_some() || b.real.is_some() {
+        return apply_real_binary(op, &a, &b);
+    }
+
+    let result = match op {
+        "&&" | "||" | "->" | "<->" => {
+            let (x, y) = (a.truth(), b.truth());
+            let v = match op {
+                "&&" => match (x, y) { (_, Some(false)) => Some(false), (Some(x), Some(y)) => Some(x && y), _ => None },
+                "||" => match (x, y) { (_, Some(true)) => Some(true), (Some(x), Some(y)) => Some(x || y), _ => None },
+                "->" => match (x, y) { (Some(false), _) | (_, Some(true)) => Some(true), (Some(x), Some(y)) => Some(!x || y), _ => None },
+                _ => x.zip(y).map(|(x, y)| x == y),
+            };
+            v.map_or(SvValue::unknown(1, false), SvValue::from_bool)
+        }
+        "<<" | ">>" | "<<<" | ">>>" => {
+            if !b.is_known() {
+                return Some(SvValue::unknown(a.width, a.signed));
+            }
+            let n = b.bits.to_u32().unwrap_or(u32::MAX).min(a.width);
+            match op {
+                "<<" | "<<<" => SvValue::new(&a.bits << n, &a.xz << n, a.width, a.signed),
+                ">>>" if a.signed => {
+                    // Arithmetic shift fills with the sign bit, known or not
+                    let fill = width_mask(a.width) ^ width_mask(a.width - n);
+                    let top = a.width as u64 - 1;
+                    let bits = if a.bits.bit(top) { (&a.bits >> n) | &fill } else { &a.bits >> n };
+                    let xz = if a.xz.bit(top) { (&a.xz >> n) | &fill } else { &a.xz >> n };
+                    SvValue::new(bits, xz, a.width, a.signed)
+                }
+                _ => SvValue::new(&a.bits >> n, &a.xz >> n, a.width, a.signed),
+            }
+        }
+        "**" => {
+            if !a.is_known() || !b.is_known() {
+                return Some(SvValue::unknown(a.width, a.signed));
+            }
+            pow(&a, &b)
+        }
+        _ => {
+            // Context width and signedness shared by both operands
+            let width = a.width.max(b.width);
+            let signed = a.signed && b.signed;
+            // Operands take the signedness of the expression before they are extended
+            let (a, b) = (a.with_signed(signed).resize(width), b.with_signed(signed).resize(width));
+            let known = a.is_known() && b.is_known();
+            let zero = BigUint::zero();
+            let ordered = |f: fn(std::cmp::Ordering) -> bool| {
+                if known {
+                    SvValue::from_bool(f(a.as_bigint().cmp(&b.as_bigint())))
+                } else {
+                    SvValue::unknown(1, false)
+                }
+            };
+            match op {
+                "+" | "-" | "*" | "/" | "%" if !known => SvValue::unknown(width, signed),
+                "+" => SvValue::new(&a.bits + &b.bits, zero, width, signed),
+                "-" => SvValue::new((BigUint::one() << width) + &a.bits - &b.bits, zero, width, signed),
+                "*" => SvValue::new(&a.bits * &b.bits, zero, width, signed),
+                "/" | "%" if b.bits.is_zero() => SvValue::unknown(width, signed),
+                "/" => SvValue::from_bigint(&(a.as_bigint() / b.as_bigint()), width, signed),
+                "%" => SvValue::from_bigint(&(a.as_bigint() % b.as_bigint()), width, signed),
+                "&" => {
+                    let ones = &a.bits & &b.bits;
+                    let unknown = width_mask(width) ^ (&ones | (a.zeros() | b.zeros()));
+                    SvValue::new(ones, unknown, width, signed)
+                }
+                "|" => {
+                    let ones = &a.bits | &b.bits;
+                    let unknown = width_mask(width) ^ (&ones | (a.zeros() & b.zeros()));
+                    SvValue::new(ones, unknown, width, signed)
+                }
+                "^" => SvValue::new(&a.bits ^ &b.bits, &a.xz | &b.xz, width, signed),
+                "^~" | "~^" => SvValue::new(width_mask(width) ^ &a.bits ^ &b.bits, &a.xz | &b.xz, width, signed),
+                "===" => SvValue::from_bool(a.bits == b.bits && a.xz == b.xz),
+                "!==" => SvValue::from_bool(a.bits != b.bits || a.xz != b.xz),
+                "==?" | "!=?" => {
+                    // `x`/`z` bits of the right operand are wildcards
+                    let care = width_mask(width) ^ &b.xz;
+                    let v = if !((&a.bits ^ &b.bits) & &care & (width_mask(width) ^ &a.xz)).is_zero() {
+                        Some(false)
+                    } else if (&a.xz & &care).is_zero() {
+                        Some(true)
+                    } else {
+                        None
+                    };
+                    v.map_or(SvValue::unknown(1, false), |v| SvValue::from_bool(v == (op == "==?")))
+                }
+                "==" => ordered(|o| o.is_eq()),
+                "!=" => ordered(|o| o.is_ne()),
+                "<" => ordered(|o| o.is_lt()),
+                "<=" => ordered(|o| o.is_le()),
+                ">" => ordered(|o| o.is_gt()),
+                ">=" => ordered(|o| o.is_ge()),
+                _ => return None,
+            }
+        }
+    };
+    Some(result)
+}
+
+/// Operators with a real operand; the other operand is converted to real.
+fn apply_real_binary(op: &str, a: &SvValue, b: &SvValue) -> Option<SvValue> {
+    let (Some(x), Some(y)) = (a.to_f64(), b.to_f64()) else {
+        return Some(SvValue::unknown(64, true));
+    };
+    let result = match op {
+        "+" => SvValue::from_real(x + y),
+        "-" => SvValue::from_real(x - y),
+        "*" => SvValue::from_real(x * y),
+        "/" => SvValue::from_real(x / y),
+        "**" => SvValue::from_real(x.powf(y)),
+        "<" => SvValue::from_bool(x < y),
+        "<=" => SvValue::from_bool(x <= y),
+        ">" => SvValue::from_bool(x > y),
+        ">=" => SvValue::from_bool(x >= y),
+        "==" => SvValue::from_bool(x == y),
+        "!=" => SvValue::from_bool(x != y),
+        "&&" => SvValue::from_bool(x != 0.0 && y != 0.0),
+        "||" => SvValue::from_bool(x != 0.0 || y != 0.0),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// The result is as wide as the wider branch, whichever is chosen.
+fn apply_ternary(condition: Option<SvValue>, a: Option<SvValue>, b: Option<SvValue>) -> Option<SvValue> {
+    match condition?.truth() {
+        Some(truth) => {
+            let (chosen, other) = if truth { (a?, b) } else { (b?, a) };
+            match other {
+                Some(other) if chosen.real.is_none() && other.real.is_none() => {
+                    let signed = chosen.signed && other.signed;
+                    Some(chosen.with_signed(signed).resize(chosen.width.max(other.width)))
+                }
+                _ => Some(chosen),
+            }
+        }
+        None => {
+            // Unknown condition: bits on which both branches agree stay known
+            let (a, b) = (a?.integral()?, b?.integral()?);
+            let width = a.width.max(b.width);
+            let signed = a.signed && b.signed;
+            let (a, b) = (a.with_signed(signed).resize(width), b.with_signed(signed).resize(width));
+            let xz = &a.xz | &b.xz | (&a.bits ^ &b.bits);
+            Some(SvValue::new(a.bits, xz, width, signed))
+        }
+    }
+}
+
+fn concatenate(values: Vec<SvValue>) -> Option<SvValue> {
+    if values.iter().any(|v| v.real.is_some()) {
+        return None;
+    }
+    let width: u64 = values.iter().map(|v| v.width as u64).sum();
+    if width == 0 || width > MAX_WIDTH as u64 {
+        return None;
+    }
+    let (bits, xz) = values.iter().fold((BigUint::zero(), BigUint::zero()), |(bits, xz), v| {
+        ((bits << v.width) | &v.bits, (xz << v.width) | &v.xz)
+    });
+    Some(SvValue::new(bits, xz, width as u32, false))
+}
+
+/// Value of a literal such as `12`, `8'hFF`, `4'sb10x1`, `1.5e3` or `'1`. Unsized
+/// literals are at least 32 bits wide and grow to fit their digits.
+pub fn parse_literal(text: &str) -> Option<SvValue> {
+    lazy_static! {
+        static ref SV_NUM_RE: Regex = Regex::new(r"(?i)^(?:(\d[\d_]*)\s*)?'(s?)([dhbo])\s*([0-9a-f_xz?]+)$").unwrap();
+        static ref SV_DEC_RE: Regex = Regex::new(r"^\d[\d_]*$").unwrap();
+        static ref SV_REAL_RE: Regex = Regex::new(r"^\d[\d_]*(\.\d[\d_]*)?([eE][+-]?\d[\d_]*)?$").unwrap();
+    }
+
+    let text = text.trim();
+    if !SV_DEC_RE.is_match(text) && SV_REAL_RE.is_match(text) {
+        return text.replace('_', "").parse::<f64>().ok().map(SvValue::from_real);
+    }
+    if SV_DEC_RE.is_match(text) {
+        let v = BigUint::parse_bytes(text.replace('_', "").as_bytes(), 10)?;
+        let width = INTEGER_WIDTH.max(v.bits() as u32 + 1);
+        return (width <= MAX_WIDTH).then(|| SvValue::new(v, BigUint::zero(), width, true));
+    }
+    match text {
+        "'0" => return Some(SvValue::new(BigUint::zero(), BigUint::zero(), 1, false)),
+        "'1" => return Some(SvValue::new(BigUint::one(), BigUint::zero(), 1, false)),
+        "'x" | "'X" | "'z" | "'Z" => return Some(SvValue::unknown(1, false)),
+        _ => (),
+    }
+
+    let caps = SV_NUM_RE.captures(text)?;
+    let signed = !caps[2].is_empty();
+    let base = caps[3].to_ascii_lowercase();
+    let digits = caps[4].replace('_', "").to_ascii_lowercase();
+    let is_unknown = |c: char| matches!(c, 'x' | 'z' | '?');
+
+    let (bits, xz, digits_width) = if base == "d" {
+        if digits.chars().all(is_unknown) {
+            (BigUint::zero(), width_mask(INTEGER_WIDTH), 0)
+        } else {
+            let bits = BigUint::parse_bytes(digits.as_bytes(), 10)?;
+            let width = bits.bits() as u32;
+            (bits, BigUint::zero(), width)
+        }
+    } else {
+        let (radix, max_digit) = match base.as_str() {
+            "h" => (16, 'f'),
+            "o" => (8, '7'),
+            _ => (2, '1'),
+        };
+        let known: String = digits.chars().map(|c| if is_unknown(c) { '0' } else { c }).collect();
+        let unknown: String = digits.chars().map(|c| if is_unknown(c) { max_digit } else { '0' }).collect();
+        let bits = BigUint::parse_bytes(known.as_bytes(), radix)?;
+        let xz = BigUint::parse_bytes(unknown.as_bytes(), radix)?;
+        (bits, xz, digits.len() as u32 * radix.trailing_zeros())
+    };
+
+    let width = match caps.get(1) {
+        Some(size) => size.as_str().replace('_', "").parse::<u32>().ok()?,
+        None => INTEGER_WIDTH.max(digits_width),
+    };
+    if width == 0 || width > MAX_WIDTH {
+        return None;
+    }
+
+    // An unknown leading digit extends to the full literal width
+    let xz = match digits.starts_with(is_unknown) && digits_width < width {
+        true => xz | (width_mask(width) ^ width_mask(digits_width)),
+        false => xz,
+    };
+
+    Some(SvValue::new(bits, xz, width, signed))
+}
+
+/// Operator or operand of a flattened binary/ternary chain. sv-parser does not
+/// apply operator precedence, so chains are re-associated from source order.
+enum Token<'b> {
+    Constant(&'b ConstantExpression),
+    Expression(&'b Expression),
+    Binary(String),
+    Question,
+    Colon,
+}
+
+/// Typedefs and defines of one compilation unit, created once and shared by
+/// every evaluation in it. Define bodies are expanded and evaluated on first
+/// lookup against the defines current at that point, and memoized until any
+/// define changes.
+pub struct EvalContext<'a> {
+    syntax_tree: &'a SyntaxTree,
+    types: TypeMap<'a>,
+    defines: HashMap<String, String>,
+    define_values: RefCell<HashMap<String, Result<SvValue, String>>>,
+    packages: HashMap<String, SvValue>,
+    // Offsets of the newlines of each source file, read once
+    line_ends: RefCell<HashMap<PathBuf, Vec<usize>>>,
+}
+
+impl<'a> EvalContext<'a> {
+    pub fn new(syntax_tree: &'a SyntaxTree, types: TypeMap<'a>) -> Self {
+        EvalContext {
+            syntax_tree,
+            types,
+            defines: HashMap::new(),
+            define_values: RefCell::new(HashMap::new()),
+            packages: HashMap::new(),
+            line_ends: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// File and line of `locate` in that file. `Locate::line` counts lines of
+    /// the preprocessed text, which includes shift.
+    pub fn location(&self, locate: &Locate) -> (Option<String>, u32) {
+        let Some((path, offset)) = self.syntax_tree.get_origin(locate) else {
+            return (None, locate.line);
+        };
+        let mut line_ends = self.line_ends.borrow_mut();
+        if !line_ends.contains_key(path) {
+            let Ok(text) = std::fs::read(path) else {
+                return (Some(path.to_string_lossy().into_owned()), locate.line);
+            };
+            line_ends.insert(path.clone(), text.iter().enumerate().filter(|(_, c)| **c == b'\n').map(|(i, _)| i).collect());
+        }
+        let line = line_ends[path].partition_point(|&end| end < offset) as u32 + 1;
+        (Some(path.to_string_lossy().into_owned()), line)
+    }
+
+    /// Add the package parameters of `values`, keyed by `pkg::NAME`.
+    pub fn add_package_values(&mut self, values: &HashMap<String, SvValue>) {
+        self.packages.extend(values.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    pub fn package_values(&self) -> &HashMap<String, SvValue> {
+        &self.packages
+    }
+
+    /// Parameters made visible by `import package::item`, or by
+    /// `import package::*` when `item` is `None`.
+    pub fn imported(&self, package: &str, item: Option<&str>) -> Vec<(String, SvValue)> {
+        let prefix = format!("{}::", package);
+        self.packages.iter()
+            .filter_map(|(k, v)| Some((k.strip_prefix(&prefix)?.to_string(), v)))
+            .filter(|(k, _)| item.is_none_or(|item| item == k))
+            .map(|(k, v)| (k, v.clone()))
+            .collect()
+    }
+
+    pub fn add_define(&mut self, name: &str, body: &str) {
+        self.defines.insert(name.to_string(), body.to_string());
+        self.define_values.get_mut().clear();
+    }
+
+    pub fn remove_define(&mut self, name: &str) {
+        self.defines.remove(name);
+        self.define_values.get_mut().clear();
+    }
+
+    pub fn define_value(&self, name: &str) -> Option<SvValue> {
+        self.eval_define(name)?.ok()
+    }
+
+    /// Value of the define `name`, or why it has none. `None` when `name` is
+    /// not defined.
+    pub fn eval_define(&self, name: &str) -> Option<Result<SvValue, String>> {
+        if !self.defines.contains_key(name) {
+            return None;
+        }
+        if let Some(value) = self.define_values.borrow().get(name) {
+            return Some(value.clone());
+        }
+        let value = self.expand_define(name, &mut Vec::new()).and_then(|body| {
+            eval_str(&body, &HashMap::new()).ok_or_else(|| format!("`{} is not a constant expression", name))
+        });
+        self.define_values.borrow_mut().insert(name.to_string(), value.clone());
+        Some(value)
+    }
+
+    /// Body of `name` with the defines it uses expanded. `active` holds the
+    /// defines being expanded, to stop on recursive definitions.
+    fn expand_define(&self, name: &str, active: &mut Vec<String>) -> Result<String, String> {
+        lazy_static! {
+            static ref MACRO_USAGE_RE: Regex = Regex::new(r"`([A-Za-z_][A-Za-z0-9_$]*)").unwrap();
+        }
+
+        if active.iter().any(|n| n == name) {
+            return Err(format!("`{} is defined recursively", name));
+        }
+        let Some(body) = self.defines.get(name) else {
+            let user = active.last().map_or(String::new(), |user| format!(" used by `{}", user));
+            return Err(format!("`{}{} is not defined", name, user));
+        };
+
+        active.push(name.to_string());
+        let mut expanded = String::new();
+        let mut last = 0;
+        for caps in MACRO_USAGE_RE.captures_iter(body) {
+            let usage = caps.get(0).unwrap();
+            let value = self.expand_define(&caps[1], active)?;
+            expanded += &body[last..usage.start()];
+            expanded += &value;
+            last = usage.end();
+        }
+        expanded += &body[last..];
+        active.pop();
+        Ok(expanded)
+    }
+
+    /// Evaluator over the compilation unit with `scope` holding the
+    /// parameters of the current module.
+    pub fn evaluator<'b>(&'b self, scope: &'b HashMap<String, SvValue>) -> ConstEvaluator<'b> {
+        ConstEvaluator { context: Some(self), ..ConstEvaluator::new(self.syntax_tree, scope) }
+    }
+}
+
+/// Evaluates constant expressions on the sv-parser AST. Names resolve to
+/// `locals` (genvars), then `scope` (parameters), then the package parameters
+/// and defines of the compilation unit. Names and types that fail to resolve
+/// are kept in `missing`, other reasons evaluation failed in `errors`.
+pub struct ConstEvaluator<'a> {
+    syntax_tree: &'a SyntaxTree,
+    scope: &'a HashMap<String, SvValue>,
+    locals: &'a [(String, SvValue)],
+    context: Option<&'a EvalContext<'a>>,
+    missing: RefCell<Vec<String>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl<'a> ConstEvaluator<'a> {
+    pub fn new(syntax_tree: &'a SyntaxTree, scope: &'a HashMap<String, SvValue>) -> Self {
+        ConstEvaluator { syntax_tree, scope, locals: &[], context: None, missing: RefCell::new(Vec::new()), errors: RefCell::new(Vec::new()) }
+    }
+
+    /// Bind genvars or other names that shadow `scope`.
+    pub fn locals(mut self, locals: &'a [(String, SvValue)]) -> Self {
+        self.locals = locals;
+        self
+    }
+
+    /// First name or type that failed to resolve so far.
+    pub fn missing(&self) -> Option<String> {
+        self.missing.borrow().first().cloned()
+    }
+
+    /// First reason, other than a missing name, an evaluation failed so far.
+    pub fn error(&self) -> Option<String> {
+        self.errors.borrow().first().cloned()
+    }
+
+    fn fail<T>(&self, reason: String) -> Option<T> {
+        self.errors.borrow_mut().push(reason);
+        None
+    }
+
+    pub fn eval(&self, expr: &ConstantExpression) -> Option<SvValue> {
+        let mut tokens = Vec::new();
+        self.flatten_constant(expr, &mut tokens);
+        self.eval_tokens(&tokens, &mut 0, 0)
+    }
+
+    pub fn eval_expression(&self, expr: &Expression) -> Option<SvValue> {
+        let mut tokens = Vec::new();
+        self.flatten_expression(expr, &mut tokens);
+        self.eval_tokens(&tokens, &mut 0, 0)
+    }
+
+    fn text<'b, T: Into<sv_parser::RefNodes<'b>>>(&self, node: T) -> String {
+        self.syntax_tree.get_str_trim(node).unwrap_or_default().split_whitespace().collect()
+    }
+
+    fn flatten_constant<'b>(&self, expr: &'b ConstantExpression, tokens: &mut Vec<Token<'b>>) {
+        match expr {
+            ConstantExpression::Binary(x) => {
+                self.flatten_constant(&x.nodes.0, tokens);
+                tokens.push(Token::Binary(self.text(&x.nodes.1)));
+                self.flatten_constant(&x.nodes.3, tokens);
+            }
+            ConstantExpression::Ternary(x) => {
+                self.flatten_constant(&x.nodes.0, tokens);
+                tokens.push(Token::Question);
+                self.flatten_constant(&x.nodes.3, tokens);
+                tokens.push(Token::Colon);
+                self.flatten_constant(&x.nodes.5, tokens);
+            }
+            _ => tokens.push(Token::Constant(expr)),
+        }
+    }
+
+    fn flatten_expression<'b>(&self, expr: &'b Expression, tokens: &mut Vec<Token<'b>>) {
+        match expr {
+            Expression::Binary(x) => {
+                self.flatten_expression(&x.nodes.0, tokens);
+                tokens.push(Token::Binary(self.text(&x.nodes.1)));
+                self.flatten_expression(&x.nodes.3, tokens);
+            }
+            Expression::ConditionalExpression(x) => {
+                match x.nodes.0.nodes.0.contents().as_slice() {
+                    [ExpressionOrCondPattern::Expression(c)] => self.flatten_expression(c, tokens),
+                    _ => tokens.push(Token::Expression(expr)),
+                }
+                tokens.push(Token::Question);
+                self.flatten_expression(&x.nodes.3, tokens);
+                tokens.push(Token::Colon);
+                self.flatten_expression(&x.nodes.5, tokens);
+            }
+            _ => tokens.push(Token::Expression(expr)),
+        }
+    }
+
+    /// Precedence climbing over a flattened chain starting at `pos`.
+    fn eval_tokens(&self, tokens: &[Token], pos: &mut usize, min_prec: u8) -> Option<SvValue> {
+        let mut lhs = match tokens.get(*pos) {
+            Some(Token::Constant(x)) => self.eval_constant_operand(x),
+            Some(Token::Expression(x)) => self.eval_expression_operand(x),
+            _ => return None,
+        };
+        *pos += 1;
+
+        loop {
+            match tokens.get(*pos) {
+                Some(Token::Binary(op)) => {
+                    let Some((prec, right_assoc)) = binary_precedence(op) else {
+                        return self.fail(format!("unsupported operator {}", op));
+                    };
+                    if prec < min_prec {
+                        break;
+                    }
+                    *pos += 1;
+                    let rhs = self.eval_tokens(tokens, pos, if right_assoc { prec } else { prec + 1 });
+                    let operands = lhs.is_some() && rhs.is_some();
+                    lhs = apply_binary(op, lhs, rhs);
+                    if operands && lhs.is_none() {
+                        return self.fail(format!("operator {} is not defined for its operands", op));
+                    }
+                }
+                Some(Token::Question) => {
+                    if TERNARY_PRECEDENCE < min_prec {
+                        break;
+                    }
+                    *pos += 1;
+                    let a = self.eval_tokens(tokens, pos, 0);
+                    if !matches!(tokens.get(*pos), Some(Token::Colon)) {
+                        return None;
+                    }
+                    *pos += 1;
+                    let b = self.eval_tokens(tokens, pos, TERNARY_PRECEDENCE);
+                    lhs = apply_ternary(lhs, a, b);
+                }
+                _ => break,
+            }
+        }
+        lhs
+    }
+
+    fn eval_constant_operand(&self, expr: &ConstantExpression) -> Option<SvValue> {
+        match expr {
+            ConstantExpression::ConstantPrimary(x) => self.eval_constant_primary(x),
+            ConstantExpression::Unary(x) => apply_unary(&self.text(&x.nodes.0), self.eval_constant_primary(&x.nodes.2)?),
+            _ => self.eval(expr),
+        }
+    }
+
+    fn eval_expression_operand(&self, expr: &Expression) -> Option<SvValue> {
+        match expr {
+            Expression::Primary(x) => self.eval_primary(x),
+            Expression::Unary(x) => apply_unary(&self.text(&x.nodes.0), self.eval_primary(&x.nodes.2)?),
+            Expression::Binary(_) | Expression::ConditionalExpression(_) => self.eval_expression(expr),
+            _ => self.unsupported(expr),
+        }
+    }
+
+    fn unsupported<'b, T: Into<sv_parser::RefNodes<'b>>>(&self, node: T) -> Option<SvValue> {
+        self.fail(format!("unsupported constant expression {}", self.text(node)))
+    }
+
+    fn lookup(&self, name: &str) -> Option<SvValue> {
+        let value = match self.locals.iter().rev().find(|(n, _)| n == name) {
+            Some((_, value)) => Some(value.clone()),
+            None => self.scope.get(name).cloned()
+                .or_else(|| self.context?.packages.get(name).cloned()),
+        };
+        if value.is_some() {
+            return value;
+        }
+        match self.context.and_then(|c| c.eval_define(name)) {
+            Some(Ok(value)) => Some(value),
+            Some(Err(reason)) => self.fail(reason),
+            None => {
+                self.missing.borrow_mut().push(name.to_string());
+                None
+            }
+        }
+    }
+
+    fn eval_literal(&self, x: &PrimaryLiteral) -> Option<SvValue> {
+        match x {
+            PrimaryLiteral::Number(_) | PrimaryLiteral::UnbasedUnsizedLiteral(_) => {
+                let text = self.text(x);
+                parse_literal(&text).or_else(|| self.fail(format!("unsupported literal {}", text)))
+            }
+            _ => self.unsupported(x),
+        }
+    }
+
+    fn eval_constant_primary(&self, x: &ConstantPrimary) -> Option<SvValue> {
+        match x {
+            ConstantPrimary::PrimaryLiteral(x) => self.eval_literal(x),
+            ConstantPrimary::PsParameter(x) => {
+                let value = self.lookup(&self.text(&x.nodes.0))?;
+                self.constant_select(value, &x.nodes.1)
+            }
+            ConstantPrimary::Specparam(x) => self.lookup(&self.text(&x.nodes.0)),
+            ConstantPrimary::GenvarIdentifier(x) => self.lookup(&self.text(x.as_ref())),
+            ConstantPrimary::FormalPort(x) => {
+                let value = self.lookup(&self.text(&x.nodes.0))?;
+                self.constant_select(value, &x.nodes.1)
+            }
+            ConstantPrimary::Enum(x) => self.lookup(&self.text(x.as_ref())),
+            ConstantPrimary::Concatenation(x) if x.nodes.1.is_none() => {
+                let values = x.nodes.0.nodes.0.nodes.1.contents().into_iter()
+                    .map(|e| self.eval(e))
+                    .collect::<Option<Vec<SvValue>>>()?;
+                concatenate(values).or_else(|| self.fail(format!("{} cannot be concatenated", self.text(x.as_ref()))))
+            }
+            ConstantPrimary::MultipleConcatenation(x) if x.nodes.1.is_none() => {
+                let (count, concat) = &x.nodes.0.nodes.0.nodes.1;
+                let count = self.eval(count)?.to_i64()?;
+                let values = concat.nodes.0.nodes.1.contents().into_iter()
+                    .map(|e| self.eval(e))
+                    .collect::<Option<Vec<SvValue>>>()?;
+                concatenate(values)
+                    .and_then(|value| concatenate(vec![value; usize::try_from(count).ok()?]))
+                    .or_else(|| self.fail(format!("{} cannot be concatenated", self.text(x.as_ref()))))
+            }
+            ConstantPrimary::ConstantFunctionCall(x) => self.eval_function_call(&x.nodes.0.nodes.0),
+            ConstantPrimary::MintypmaxExpression(x) => match &x.nodes.0.nodes.1 {
+                sv_parser::ConstantMintypmaxExpression::Unary(e) => self.eval(e),
+                sv_parser::ConstantMintypmaxExpression::Ternary(e) => self.eval(&e.nodes.2),
+            },
+            ConstantPrimary::ConstantCast(x) => {
+                let value = self.eval(&x.nodes.2.nodes.1)?;
+                self.cast(&x.nodes.0, value)
+            }
+            _ => self.unsupported(x),
+        }
+    }
+
+    fn eval_primary(&self, x: &Primary) -> Option<SvValue> {
+        match x {
+            Primary::PrimaryLiteral(x) => self.eval_literal(x),
+            Primary::Hierarchical(x) => {
+                let name = match &x.nodes.0 {
+                    Some(scope) => format!("{}{}", self.text(scope), self.text(&x.nodes.1)),
+                    None => self.text(&x.nodes.1),
+                };
+                let value = self.lookup(&name)?;
+                self.select(value, &x.nodes.2)
+            }
+            Primary::Concatenation(x) if x.nodes.1.is_none() => {
+                let values = x.nodes.0.nodes.0.nodes.1.contents().into_iter()
+                    .map(|e| self.eval_expression(e))
+                    .collect::<Option<Vec<SvValue>>>()?;
+                concatenate(values).or_else(|| self.fail(format!("{} cannot be concatenated", self.text(x.as_ref()))))
+            }
+            Primary::MultipleConcatenation(x) if x.nodes.1.is_none() => {
+                let (count, concat) = &x.nodes.0.nodes.0.nodes.1;
+                let count = self.eval_expression(count)?.to_i64()?;
+                let values = concat.nodes.0.nodes.1.contents().into_iter()
+                    .map(|e| self.eval_expression(e))
+                    .collect::<Option<Vec<SvValue>>>()?;
+                concatenate(values)
+                    .and_then(|value| concatenate(vec![value; usize::try_from(count).ok()?]))
+                    .or_else(|| self.fail(format!("{} cannot be concatenated", self.text(x.as_ref()))))
+            }
+            Primary::FunctionSubroutineCall(x) => self.eval_function_call(&x.nodes.0),
+            Primary::MintypmaxExpression(x) => match &x.nodes.0.nodes.1 {
+                MintypmaxExpression::Expression(e) => self.eval_expression(e),
+                MintypmaxExpression::Ternary(e) => self.eval_expression(&e.nodes.2),
+            },
+            Primary::Cast(x) => {
+                let value = self.eval_expression(&x.nodes.2.nodes.1)?;
+                self.cast(&x.nodes.0, value)
+            }
+            _ => self.unsupported(x),
+        }
+    }
+
+    /// Bit select `P[i]` and part selects `P[m:l]`, `P[b+:w]`, `P[b-:w]` on a
+    /// value with a zero based LSB.
+    fn part_select(&self, value: SvValue, msb: i64, lsb: i64) -> Option<SvValue> {
+        if msb < lsb || lsb < 0 {
+            return None;
+        }
+        let width = u32::try_from(msb - lsb + 1).ok()?;
+        let shift = u32::try_from(lsb).ok()?;
+        if width > MAX_WIDTH || shift >= value.width {
+            return Some(SvValue::unknown(width.min(MAX_WIDTH), false));
+        }
+        // Bits selected beyond the MSB of the value read as `x`
+        let outside = width_mask(width) ^ width_mask(value.width - shift);
+        Some(SvValue::new(value.bits >> shift, (value.xz >> shift) | outside, width, false))
+    }
+
+    fn indexed_range(&self, value: SvValue, base: i64, op: &str, width: i64) -> Option<SvValue> {
+        match op {
+            "+:" => self.part_select(value, base + width - 1, base),
+            _ => self.part_select(value, base, base - width + 1),
+        }
+    }
+
+    fn constant_select(&self, value: SvValue, select: &ConstantSelect) -> Option<SvValue> {
+        if select.nodes.0.is_some() {
+            return self.unsupported(select);
+        }
+        let value = match select.nodes.1.nodes.0.as_slice() {
+            [] => value,
+            [index] => {
+                let i = self.eval(&index.nodes.1)?.to_i64()?;
+                self.part_select(value, i, i)?
+            }
+            _ => return self.unsupported(select),
+        };
+        match select.nodes.2.as_ref().map(|x| &x.nodes.1) {
+            None => Some(value),
+            Some(ConstantPartSelectRange::ConstantRange(x)) => {
+                let msb = self.eval(&x.nodes.0)?.to_i64()?;
+                let lsb = self.eval(&x.nodes.2)?.to_i64()?;
+                self.part_select(value, msb, lsb)
+            }
+            Some(ConstantPartSelectRange::ConstantIndexedRange(x)) => {
+                let base = self.eval(&x.nodes.0)?.to_i64()?;
+                let width = self.eval(&x.nodes.2)?.to_i64()?;
+                self.indexed_range(value, base, &self.text(&x.nodes.1), width)
+            }
+        }
+    }
+
+    fn select(&self, value: SvValue, select: &Select) -> Option<SvValue> {
+        if select.nodes.0.is_some() {
+            return self.unsupported(select);
+        }
+        let value = match select.nodes.1.nodes.0.as_slice() {
+            [] => value,
+            [index] => {
+                let i = self.eval_expression(&index.nodes.1)?.to_i64()?;
+                self.part_select(value, i, i)?
+            }
+            _ => return self.unsupported(select),
+        };
+        match select.nodes.2.as_ref().map(|x| &x.nodes.1) {
+            None => Some(value),
+            Some(PartSelectRange::ConstantRange(x)) => {
+                let msb = self.eval(&x.nodes.0)?.to_i64()?;
+                let lsb = self.eval(&x.nodes.2)?.to_i64()?;
+                self.part_select(value, msb, lsb)
+            }
+            Some(PartSelectRange::IndexedRange(x)) => {
+                let base = self.eval_expression(&x.nodes.0)?.to_i64()?;
+                let width = self.eval(&x.nodes.2)?.to_i64()?;
+                self.indexed_range(value, base, &self.text(&x.nodes.1), width)
+            }
+        }
+    }
+
+    /// `int'(x)`, `8'(x)`, `signed'(x)` and `unsigned'(x)`.
+    fn cast(&self, casting_type: &CastingType, value: SvValue) -> Option<SvValue> {
+        let value = value.integral()?;
+        match casting_type {
+            CastingType::Signing(x) => Some(value.with_signed(self.text(x.as_ref()) == "signed")),
+            CastingType::ConstantPrimary(x) => {
+                let width = u32::try_from(self.eval_constant_primary(x)?.to_i64()?).ok()?;
+                if width == 0 || width > MAX_WIDTH {
+                    return None;
+                }
+                Some(value.resize(width))
+            }
+            CastingType::SimpleType(x) => match x.as_ref() {
+                SimpleType::IntegerType(_) => {
+                    let (width, signed) = match self.text(x.as_ref()).as_str() {
+                        "byte" => (8, true),
+                        "shortint" => (16, true),
+                        "int" | "integer" => (32, true),
+                        "longint" | "time" => (64, true),
+                        _ => (1, false),
+                    };
+                    Some(value.resize(width).with_signed(signed))
+                }
+                _ => self.unsupported(x.as_ref()),
+            },
+            _ => self.unsupported(casting_type),
+        }
+    }
+
+    fn eval_function_call(&self, call: &SubroutineCall) -> Option<SvValue> {
+        let x = match call {
+            SubroutineCall::SystemTfCall(x) => x,
+            // sv-parser reads an identifier followed by whitespace as a call
+            // without arguments
+            SubroutineCall::TfCall(x) if x.nodes.1.is_empty() && x.nodes.2.is_none() => {
+                return self.lookup(&self.text(&x.nodes.0));
+            }
+            _ => return self.unsupported(call),
+        };
+        let (name, args) = match x.as_ref() {
+            SystemTfCall::ArgExpression(x) => {
+                let args = x.nodes.1.nodes.1.0.contents().into_iter().flatten().map(SystemArg::Expression).collect::<Vec<_>>();
+                (self.text(&x.nodes.0), args)
+            }
+            SystemTfCall::ArgOptionl(x) => {
+                let args = match &x.nodes.1 {
+                    Some(paren) => match &paren.nodes.1 {
+                        ListOfArguments::Ordered(list) => list.nodes.0.contents().into_iter().flatten().map(SystemArg::Expression).collect(),
+                        ListOfArguments::Named(_) => return self.unsupported(call),
+                    },
+                    None => Vec::new(),
+                };
+                (self.text(&x.nodes.0), args)
+            }
+            SystemTfCall::ArgDataType(x) => {
+                let (data_type, dimension) = &x.nodes.1.nodes.1;
+                let mut args = vec![SystemArg::Type(data_type)];
+                args.extend(dimension.as_ref().map(|(_, e)| SystemArg::Expression(e)));
+                (self.text(&x.nodes.0), args)
+            }
+        };
+
+        let integer = |v: BigInt| Some(SvValue::from_bigint(&v, INTEGER_WIDTH, true));
+        match (name.as_str(), args.as_slice()) {
+            ("$clog2", [arg]) => {
+                let value = self.eval_system_arg(arg)?.integral()?;
+                if !value.is_known() {
+                    return Some(SvValue::unknown(INTEGER_WIDTH, true));
+                }
+                integer(BigInt::from(clog2(&value.as_bigint())))
+            }
+            ("$bits", [arg]) => integer(BigInt::from(self.system_arg_layout(arg)?.width())),
+            ("$size" | "$high" | "$low" | "$left" | "$right", [arg, dimension @ ..]) if dimension.len() <= 1 => {
+                let n = match dimension {
+                    [d] => usize::try_from(self.eval_system_arg(d)?.to_i64()?).ok()?,
+                    _ => 1,
+                };
+                let Some((left, right)) = self.system_arg_layout(arg)?.dimension(n) else {
+                    return self.fail(format!("{} has no dimension {}", self.system_arg_text(arg), n));
+                };
+                match name.as_str() {
+                    "$size" => integer(BigInt::from((&left - &right).magnitude() + 1u32)),
+                    "$high" => integer(left.max(right)),
+                    "$low" => integer(left.min(right)),
+                    "$left" => integer(left),
+                    _ => integer(right),
+                }
+            }
+            ("$signed" | "$unsigned", [arg]) => {
+                Some(self.eval_system_arg(arg)?.integral()?.with_signed(name == "$signed"))
+            }
+            ("$countones", [arg]) => {
+                let value = self.eval_system_arg(arg)?.integral()?;
+                integer(BigInt::from(value.bits.count_ones()))
+            }
+            ("$rtoi", [arg]) => integer(BigInt::from_f64(self.eval_system_arg(arg)?.to_f64()?.trunc())?),
+            _ => self.unsupported(call),
+        }
+    }
+
+    /// Value of a parameter declared with `data_type`, e.g. `parameter
+    /// logic [7:0] P = 300` holds `8'd44` and `parameter real R = 1` holds `1.0`.
+    /// `data_type` is a `DataType` or `DataTypeOrImplicit` node.
+    pub fn convert(&self, data_type: RefNode, value: SvValue) -> Option<SvValue> {
+        let data_type = match data_type {
+            RefNode::DataTypeOrImplicit(DataTypeOrImplicit::DataType(x)) => x.as_ref(),
+            RefNode::DataType(x) => x,
+            RefNode::DataTypeOrImplicit(DataTypeOrImplicit::ImplicitDataType(x)) => {
+                return self.convert_implicit(x, value);
+            }
+            _ => return Some(value),
+        };
+        match data_type {
+            DataType::NonIntegerType(_) => Some(SvValue::from_real(value.to_f64()?)),
+            x => self.resize_to_layout(&self.data_type_layout(x)?, value),
+        }
+    }
+
+    /// `parameter signed P`, `parameter [7:0] P`; without either the value
+    /// keeps its own type.
+    fn convert_implicit(&self, x: &ImplicitDataType, value: SvValue) -> Option<SvValue> {
+        let signed = self.is_signed(&x.nodes.0, false);
+        match (&x.nodes.0, x.nodes.1.is_empty()) {
+            (None, true) => Some(value),
+            (Some(_), true) => Some(value.integral()?.with_signed(signed)),
+            _ => {
+                let layout = Layout { dimensions: self.packed_dimensions(&x.nodes.1)?, ..Layout::scalar(1, signed) };
+                self.resize_to_layout(&layout, value)
+            }
+        }
+    }
+
+    fn resize_to_layout(&self, layout: &Layout, value: SvValue) -> Option<SvValue> {
+        let width = layout.width().to_u32().filter(|w| (1..=MAX_WIDTH).contains(w))?;
+        Some(value.integral()?.resize(width).with_signed(layout.signed))
+    }
+
+    fn system_arg_text(&self, arg: &SystemArg) -> String {
+        match arg {
+            SystemArg::Type(x) => self.text(*x),
+            SystemArg::Expression(x) => self.text(*x),
+        }
+    }
+
+    fn eval_system_arg(&self, arg: &SystemArg) -> Option<SvValue> {
+        match arg {
+            SystemArg::Expression(x) => self.eval_expression(x),
+            SystemArg::Type(x) => self.unsupported(*x),
+        }
+    }
+
+    /// Layout of a data type, a typedef name or the value of an expression.
+    fn system_arg_layout(&self, arg: &SystemArg) -> Option<Layout> {
+        match arg {
+            SystemArg::Type(x) => self.data_type_layout(x),
+            SystemArg::Expression(x) => {
+                let name = self.text(*x);
+                if self.lookup_type(&name).is_some() {
+                    return self.typedef_layout(&name);
+                }
+                let value = self.eval_expression(x)?.integral()?;
+                Some(Layout::scalar(value.width as u64, value.signed))
+            }
+        }
+    }
+
+    fn lookup_type(&self, name: &str) -> Option<&'a TypeDeclarationDataType> {
+        let types = &self.context?.types;
+        types.get(name).or_else(|| types.get(name.rsplit("::").next()?)).copied()
+    }
+
+    fn typedef_layout(&self, name: &str) -> Option<Layout> {
+        let Some(typedef) = self.lookup_type(name) else {
+            self.missing.borrow_mut().push(name.to_string());
+            return None;
+        };
+        let mut layout = self.data_type_layout(&typedef.nodes.1)?;
+        let mut dimensions = self.variable_dimensions(&typedef.nodes.3)?;
+        dimensions.append(&mut layout.dimensions);
+        layout.dimensions = dimensions;
+        Some(layout)
+    }
+
+    fn data_type_layout(&self, x: &DataType) -> Option<Layout> {
+        let (mut layout, packed): (Layout, &[PackedDimension]) = match x {
+            DataType::Vector(x) => (Layout::scalar(1, self.is_signed(&x.nodes.1, false)), &x.nodes.2),
+            DataType::Atom(x) => (self.atom_layout(&x.nodes.0, &x.nodes.1), &[]),
+            DataType::NonIntegerType(x) => match x.as_ref() {
+                NonIntegerType::Shortreal(_) => (Layout::scalar(32, true), &[]),
+                _ => (Layout::scalar(64, true), &[]),
+            },
+            DataType::StructUnion(x) => {
+                let (first, rest) = &x.nodes.2.nodes.1;
+                let mut widths = Vec::new();
+                for member in std::iter::once(first).chain(rest) {
+                    let DataTypeOrVoid::DataType(data_type) = &member.nodes.2 else {
+                        continue;
+                    };
+                    let width = self.data_type_layout(data_type)?.width();
+                    for decl in member.nodes.3.nodes.0.contents() {
+                        let VariableDeclAssignment::Variable(v) = decl else {
+                            return self.unsupported_type(x.as_ref());
+                        };
+                        let array = Layout { dimensions: self.variable_dimensions(&v.nodes.1)?, element_width: width.clone(), signed: false };
+                        widths.push(array.width());
+                    }
+                }
+                let width = match &x.nodes.0 {
+                    StructUnion::Struct(_) => widths.into_iter().sum(),
+                    _ => widths.into_iter().max().unwrap_or_default(),
+                };
+                let signed = x.nodes.1.as_ref().is_some_and(|(_, signing)| self.is_signed(signing, false));
+                (Layout { dimensions: Vec::new(), element_width: width, signed }, &x.nodes.3)
+            }
+            DataType::Enum(x) => {
+                let base = match &x.nodes.1 {
+                    None => Layout::scalar(32, true),
+                    Some(EnumBaseType::Atom(x)) => self.atom_layout(&x.nodes.0, &x.nodes.1),
+                    Some(EnumBaseType::Vector(x)) => Layout {
+                        dimensions: self.packed_dimensions(x.nodes.2.as_slice())?,
+                        ..Layout::scalar(1, self.is_signed(&x.nodes.1, false))
+                    },
+                    Some(EnumBaseType::Type(x)) => {
+                        let mut layout = self.typedef_layout(&self.text(&x.nodes.0))?;
+                        let mut dimensions = self.packed_dimensions(x.nodes.1.as_slice())?;
+                        dimensions.append(&mut layout.dimensions);
+                        Layout { dimensions, ..layout }
+                    }
+                };
+                (base, &x.nodes.3)
+            }
+            DataType::Type(x) => {
+                let name = match &x.nodes.0 {
+                    Some(scope) => format!("{}{}", self.text(scope), self.text(&x.nodes.1)),
+                    None => self.text(&x.nodes.1),
+                };
+                (self.typedef_layout(&name)?, &x.nodes.2)
+            }
+            // sv-parser cannot tell a typedef name from a class name
+            DataType::ClassType(x) if self.lookup_type(&self.text(x.as_ref())).is_some() => {
+                (self.typedef_layout(&self.text(x.as_ref()))?, &[])
+            }
+            _ => return self.unsupported_type(x),
+        };
+        let mut dimensions = self.packed_dimensions(packed)?;
+        dimensions.append(&mut layout.dimensions);
+        layout.dimensions = dimensions;
+        Some(layout)
+    }
+
+    fn atom_layout(&self, x: &IntegerAtomType, signing: &Option<Signing>) -> Layout {
+        let width = match x {
+            IntegerAtomType::Byte(_) => 8,
+            IntegerAtomType::Shortint(_) => 16,
+            IntegerAtomType::Int(_) | IntegerAtomType::Integer(_) => 32,
+            IntegerAtomType::Longint(_) | IntegerAtomType::Time(_) => 64,
+        };
+        let signed = self.is_signed(signing, !matches!(x, IntegerAtomType::Time(_)));
+        Layout::scalar(width, signed)
+    }
+
+    fn is_signed(&self, signing: &Option<Signing>, default: bool) -> bool {
+        match signing {
+            Some(x) => self.text(x) == "signed",
+            None => default,
+        }
+    }
+
+    fn unsupported_type<'b, T: Into<sv_parser::RefNodes<'b>>>(&self, node: T) -> Option<Layout> {
+        self.fail(format!("unsupported data type {}", self.text(node)))
+    }
+
+    fn range_bounds(&self, left: &ConstantExpression, right: &ConstantExpression) -> Option<(BigInt, BigInt)> {
+        Some((self.eval(left)?.to_bigint()?, self.eval(right)?.to_bigint()?))
+    }
+
+    fn packed_dimensions(&self, dimensions: &[PackedDimension]) -> Option<Vec<(BigInt, BigInt)>> {
+        dimensions.iter().map(|d| match d {
+            PackedDimension::Range(x) => self.range_bounds(&x.nodes.0.nodes.1.nodes.0, &x.nodes.0.nodes.1.nodes.2),
+            PackedDimension::UnsizedDimension(_) => None,
+        }).collect()
+    }
+
+    /// Fixed-size unpacked dimensions; `[N]` is `[0:N-1]`.
+    fn variable_dimensions(&self, dimensions: &[VariableDimension]) -> Option<Vec<(BigInt, BigInt)>> {
+        dimensions.iter().map(|d| match d {
+            VariableDimension::UnpackedDimension(x) => match x.as_ref() {
+                UnpackedDimension::Range(x) => self.range_bounds(&x.nodes.0.nodes.1.nodes.0, &x.nodes.0.nodes.1.nodes.2),
+                UnpackedDimension::Expression(x) => {
+                    let size = self.eval(&x.nodes.0.nodes.1)?.to_bigint()?;
+                    Some((BigInt::zero(), size - 1))
+                }
+            },
+            _ => None,
+        }).collect()
+    }
+}
+
+/// Evaluate the text of an expression, e.g. a define body or a parameter
+/// value given on the command line.
+pub fn eval_str(text: &str, scope: &HashMap<String, SvValue>) -> Option<SvValue> {
+    if let Some(value) = parse_literal(text) {
+        return Some(value);
+    }
+
+    let source = format!("localparam __sv_extractor_expr = {};\n", text);
+    let (syntax_tree, _) = parse_sv_str(&source, PathBuf::from("<expression>"), &HashMap::new(), &Vec::<PathBuf>::new(), false, false).ok()?;
+    let expr = unwrap_node!(&syntax_tree, ConstantExpression);
+    match expr {
+        Some(RefNode::ConstantExpression(x)) => ConstEvaluator::new(&syntax_tree, scope).eval(x),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Option<i64> {
+        let scope = HashMap::from([
+            ("WIDTH".to_string(), SvValue::from_i64(16)),
+            ("NEG".to_string(), SvValue::from_i64(-8)),
+        ]);
+        eval_str(text, &scope).and_then(|v| v.to_i64())
+    }
+
+    #[test]
+    fn eval_precedence_and_ternary() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7));
+        assert_eq!(eval("2 ** 3 ** 2"), Some(64));
+        // 负指数：底数为 1、-1、0 和其他值
+        assert_eq!(eval("1 ** -3"), Some(1));
+        assert_eq!(eval("-1 ** -3"), Some(-1));
+        assert_eq!(eval("(-1) ** -2"), Some(1));
+        assert_eq!(eval("0 ** -1"), None);
+        assert_eq!(eval("2 ** -1"), Some(0));
+        assert_eq!(eval("WIDTH - 1 << 1"), Some(30));
+        assert_eq!(eval("1 | 2 & 3 == 3"), Some(1));
+        assert_eq!(eval("WIDTH > 8 ? WIDTH / 2 : 1"), Some(8));
+        assert_eq!(eval("0 ? 1 : 0 ? 2 : 3"), Some(3));
+        assert_eq!(eval("(1 << 12) - 1"), Some(4095));
+        assert_eq!(eval("$clog2(WIDTH) + $clog2(1)"), Some(4));
+    }
+
+    #[test]
+    fn eval_sizing_and_signedness() {
+        assert_eq!(eval("4'hF + 4'h1"), Some(0));
+        assert_eq!(eval("4'hF + 1"), Some(16));
+        assert_eq!(eval("NEG >>> 1"), Some(-4));
+        assert_eq!(eval("8'hF0 >>> 4"), Some(15));
+        assert_eq!(eval("4'sb1000 >>> 1"), Some(-4));
+        // 无符号表达式中有符号操作数按零扩展
+        assert_eq!(eval("4'sb1000 + 8'd0"), Some(8));
+        assert_eq!(eval("4'sb1111 == 8'hFF"), Some(0));
+        assert_eq!(eval("4'sb1111 == 8'sb1111_1111"), Some(1));
+        assert_eq!(eval("-1 < 0"), Some(1));
+        assert_eq!(eval("-1 < 0u"), None);
+        assert_eq!(eval("4'(WIDTH + 1)"), Some(1));
+        assert_eq!(eval("unsigned'(NEG) > 0"), Some(1));
+        assert_eq!(eval("8'd512"), Some(0));
+        assert_eq!(eval("{4'hA, 4'h5}"), Some(0xA5));
+        assert_eq!(eval("{2{2'b10}}"), Some(0b1010));
+        assert_eq!(eval("&4'hF + |4'h0 + ^3'b111"), Some(0));
+        assert_eq!(eval("WIDTH[4]"), Some(1));
+    }
+
+    #[test]
+    fn eval_four_state() {
+        assert_eq!(eval("4'b10x1 + 1"), None);
+        assert_eq!(eval("4'b10x1 === 4'b10x1"), Some(1));
+        assert_eq!(eval("4'b1001 ==? 4'b10x1"), Some(1));
+        assert_eq!(eval("4'b10x1 & 4'b0100"), Some(0));
+        assert_eq!(eval("1'bx ? 4'hA : 4'hA"), Some(10));
+        assert_eq!(eval("0 && 1'bx"), Some(0));
+    }
+
+    #[test]
+    fn eval_wide_values() {
+        let show = |text: &str| eval_str(text, &HashMap::new()).map(|v| v.to_string());
+
+        assert_eq!(show("128'hFFFF_FFFF_FFFF_FFFF_FFFF"), Some("1208925819614629174706175".to_string()));
+        assert_eq!(show("1 << 100"), Some("0".to_string()));
+        assert_eq!(show("128'd1 << 100"), Some("1267650600228229401496703205376".to_string()));
+        assert_eq!(show("'hFFFF_FFFF_FFFF"), Some("281474976710655".to_string()));
+        assert_eq!(show("-65'sd1 >>> 64"), Some("-1".to_string()));
+        assert_eq!(show("$clog2(128'h1_0000_0000_0000_0000_0000)"), Some("80".to_string()));
+        assert_eq!(show("8'hx5"), Some("8'hx5".to_string()));
+        assert_eq!(show("8'bz"), Some("8'hxx".to_string()));
+        assert_eq!(show("4'b1x01 | 4'b0100"), Some("13".to_string()));
+        assert_eq!(show("4'b1x01 & 4'b1100"), Some("4'b1x00".to_string()));
+        assert_eq!(show("{4'b1x01, 4'b0}"), Some("8'b1x010000".to_string()));
+        // 条件运算的结果取两个分支中较宽的宽度
+        assert_eq!(show("{1 ? 4'hF : 8'h00, 4'h0}"), Some("240".to_string()));
+        assert_eq!(show("{0 ? 8'h00 : 4'sb1111, 4'h0}"), Some("240".to_string()));
+        assert_eq!(show("{1 ? 4'sb1111 : 8'sh00, 4'h0}"), Some("4080".to_string()));
+    }
+}
@@ -1,12 +1,164 @@
-use std::{fs, path::PathBuf};
+use std::{collections::HashMap, error::Error, fs, path::{Path, PathBuf}, str::FromStr};
 
 use indoc::formatdoc;
+use num_bigint::BigInt;
 
-use crate::hdl_info::{HdlInfo, Module, Port};
+use crate::backend::{Backend, emit_files};
+use crate::clock_domain::{ClockDomain, clock_domains};
+use crate::hdl_info::{HdlInfo, Module, Package, Port};
+use crate::scala_expr::range_width;
 
-#[derive(Default)]
+/// Chisel release the generated code has to compile with.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ChiselVersion {
+    /// chisel3 3.5 and 3.6
+    Chisel3,
+    /// CIRCT-based Chisel 5, 6 and 7
+    #[default]
+    Chisel5,
+}
+
+impl FromStr for ChiselVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start_matches(['v', 'V']) {
+            "3" | "3.5" | "3.6" => Ok(ChiselVersion::Chisel3),
+            "5" | "6" | "7" => Ok(ChiselVersion::Chisel5),
+            _ => Err(format!("Unsupported Chisel version {}, expected 3, 5, 6 or 7", s)),
+        }
+    }
+}
+
+impl ChiselVersion {
+    /// Import lines for `chisel3._`, the members of `chisel3.experimental`
+    /// used and `chisel3.util._`. chisel3 3.x keeps `FlatIO` in
+    /// `experimental` and gets a wildcard import there, later releases get
+    /// the members by name.
+    fn imports(&self, experimental: &[&str], util: bool) -> String {
+        let mut lines = vec!["import chisel3._".to_string()];
+        let experimental = experimental.iter()
+            .filter(|m| *self == ChiselVersion::Chisel3 || **m != "FlatIO")
+            .collect::<Vec<&&str>>();
+        match (self, experimental.as_slice()) {
+            (_, []) => {}
+            (ChiselVersion::Chisel3, _) => lines.push("import chisel3.experimental._".to_string()),
+            (ChiselVersion::Chisel5, [member]) => lines.push(format!("import chisel3.experimental.{}", member)),
+            (ChiselVersion::Chisel5, members) => {
+                let members = members.iter().map(|m| m.to_string()).collect::<Vec<String>>();
+                lines.push(format!("import chisel3.experimental.{{{}}}", members.join(", ")));
+            }
+        }
+        if util {
+            lines.push("import chisel3.util._".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+/// How a generated BlackBox points the Scala build at its RTL source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtlSource {
+    /// As a resource named like the file, to be copied into the resources
+    Resource,
+    /// By the path of the file
+    Path,
+}
+
+impl FromStr for RtlSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resource" => Ok(RtlSource::Resource),
+            "path" => Ok(RtlSource::Path),
+            _ => Err(format!("Unsupported RTL source {}, expected resource or path", s)),
+        }
+    }
+}
+
+/// Where the Scala backends put their files: the `package` clause, the
+/// matching directories below the backend directory, and whether all
+/// classes and objects go into a single file named after the package.
+#[derive(Clone, Default)]
+pub struct ScalaLayout {
+    package: Option<String>,
+    single_file: bool,
+}
+
+impl ScalaLayout {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    pub fn package(mut self, package: &str) -> Self {
+        self.package = Some(package.to_string());
+        self
+    }
+
+    pub fn single_file(mut self) -> Self {
+        self.single_file = true;
+        self
+    }
+
+    /// `target` followed by a directory per package segment.
+    fn directory(&self, target: PathBuf) -> PathBuf {
+        match &self.package {
+            Some(package) => package.split('.').fold(target, |path, segment| path.join(segment)),
+            None => target,
+        }
+    }
+
+    /// File of the package with the code of `sources`, whose imports are
+    /// merged at the top. A `package` clause in the sources is replaced.
+    fn file(&self, sources: &[String]) -> String {
+        let mut imports: Vec<&str> = Vec::new();
+        let mut bodies = Vec::new();
+        for source in sources {
+            let mut lines = source.lines().peekable();
+            let mut offset = 0;
+            while let Some(line) = lines.next_if(|l| l.is_empty() || l.starts_with("package ") || l.starts_with("import ")) {
+                offset += line.len() + 1;
+                if line.starts_with("import ") && !imports.contains(&line) {
+                    imports.push(line);
+                }
+            }
+            bodies.push(&source[offset.min(source.len())..]);
+        }
+        let mut file = String::new();
+        if let Some(package) = &self.package {
+            file += &format!("package {}\n\n", package);
+        }
+        if !imports.is_empty() {
+            file += &format!("{}\n\n", imports.join("\n"));
+        }
+        file.push_str(&bodies.join("\n"));
+        file
+    }
+
+    /// Write the modules and shared files of `backend`, one file each or
+    /// all into `<last package segment>.scala`.
+    fn emit<B: Backend>(&self, backend: &B, output: &Path, hdl_info: &HdlInfo) -> Result<(), Box<dyn Error>> {
+        if !self.single_file {
+            return emit_files(backend, output, hdl_info);
+        }
+        let mut sources = backend.emit_shared(hdl_info).into_iter().map(|(_, c)| c).collect::<Vec<String>>();
+        for module in hdl_info.get_modules() {
+            sources.push(backend.emit_module(module, hdl_info)?);
+        }
+        let name = self.package.as_ref().and_then(|p| p.rsplit('.').next()).unwrap_or("BlackBoxes");
+        write_to_file(&output.join(backend.directory()), name, &self.file(&sources), backend.file_extension());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct ChiselConverter {
-    split_bundle: bool
+    split_bundle: bool,
+    ext_module: bool,
+    version: ChiselVersion,
+    rtl_source: Option<RtlSource>,
+    layout: ScalaLayout
 }
 
 impl ChiselConverter {
@@ -14,36 +166,157 @@ impl ChiselConverter {
         Self::default()
     }
 
+    pub fn version(mut self, version: ChiselVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn layout(mut self, layout: ScalaLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
     pub fn split_bundle(mut self) -> Self {
         self.split_bundle = true;
         self
     }
 
-    pub fn emit_chisel_string(&self, hdl_info: &HdlInfo) -> Vec<String> {
-        hdl_info.get_modules().iter().map(|m| {
-            module_to_chisel(m, self.split_bundle)
-        }).collect::<Vec<String>>()
+    /// Add the file each module was parsed from with `HasBlackBoxResource`
+    /// or `HasBlackBoxPath` (`HasExtModule*` for ExtModules).
+    pub fn rtl_source(mut self, rtl_source: RtlSource) -> Self {
+        self.rtl_source = Some(rtl_source);
+        self
+    }
+
+    /// Emit an `ExtModule` with top-level ports named exactly like the SV
+    /// ports instead of a `BlackBox` with an `io` bundle.
+    pub fn ext_module(mut self) -> Self {
+        self.ext_module = true;
+        self
+    }
+}
+
+impl Backend for ChiselConverter {
+    fn name(&self) -> &str {
+        match (self.ext_module, self.split_bundle) {
+            (true, _) => "chisel-ext-module",
+            (false, true) => "chisel-split-bundle",
+            (false, false) => "chisel",
+        }
+    }
+
+    fn file_extension(&self) -> &str {
+        "scala"
+    }
+
+    fn directory(&self) -> PathBuf {
+        if self.ext_module {
+            self.layout.directory(PathBuf::from("chisel").join("ext_module"))
+        } else {
+            self.layout.directory(split_bundle_directory("chisel", self.split_bundle))
+        }
+    }
+
+    fn emit_module(&self, module: &Module, _hdl_info: &HdlInfo) -> Result<String, Box<dyn Error>> {
+        if self.ext_module {
+            Ok(self.layout.file(&[module_to_chisel_ext_module(module, self)]))
+        } else {
+            Ok(self.layout.file(&[module_to_chisel(module, self)]))
+        }
+    }
+
+    fn emit_shared(&self, hdl_info: &HdlInfo) -> Vec<(String, String)> {
+        scala_shared_files(hdl_info).into_iter().map(|(n, c)| (n, self.layout.file(&[c]))).collect()
+    }
+
+    fn file_name(&self, module: &Module) -> String {
+        module_scala_name(module).plain
     }
 
-    pub fn emit_chisel(&self, path: &PathBuf, hdl_info: &HdlInfo) {
-        hdl_info.get_modules().iter().for_each(|m| {
-            write_to_file(&path, &m.get_name(), &module_to_chisel(m, self.split_bundle), "scala");
-        });
+    fn warnings(&self, hdl_info: &HdlInfo) -> Vec<String> {
+        scala_renames(hdl_info)
     }
 
-    pub fn emit_spinal(&self, path: &PathBuf, hdl_info: &HdlInfo) {
-        hdl_info.get_modules().iter().for_each(|m| {
-            write_to_file(&path, &m.get_name(), &module_to_spinal(m, self.split_bundle), "scala");
-        });
+    fn emit(&self, output: &Path, hdl_info: &HdlInfo) -> Result<(), Box<dyn Error>> {
+        self.layout.emit(self, output, hdl_info)
     }
 }
 
-fn write_to_file(path: &PathBuf, name: &str, contents: &str, suffix: &str) {
-    if let Err(e) = fs::create_dir_all(&path) {
+#[derive(Clone, Default)]
+pub struct SpinalConverter {
+    split_bundle: bool,
+    rtl_path: bool,
+    layout: ScalaLayout
+}
+
+impl SpinalConverter {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    pub fn layout(mut self, layout: ScalaLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn split_bundle(mut self) -> Self {
+        self.split_bundle = true;
+        self
+    }
+
+    /// Add the file each module was parsed from with `addRTLPath`.
+    pub fn rtl_path(mut self) -> Self {
+        self.rtl_path = true;
+        self
+    }
+}
+
+impl Backend for SpinalConverter {
+    fn name(&self) -> &str {
+        if self.split_bundle { "spinal-split-bundle" } else { "spinal" }
+    }
+
+    fn file_extension(&self) -> &str {
+        "scala"
+    }
+
+    fn directory(&self) -> PathBuf {
+        self.layout.directory(split_bundle_directory("spinal", self.split_bundle))
+    }
+
+    fn emit_module(&self, module: &Module, _hdl_info: &HdlInfo) -> Result<String, Box<dyn Error>> {
+        Ok(self.layout.file(&[module_to_spinal(module, self)]))
+    }
+
+    fn emit_shared(&self, hdl_info: &HdlInfo) -> Vec<(String, String)> {
+        scala_shared_files(hdl_info).into_iter().map(|(n, c)| (n, self.layout.file(&[c]))).collect()
+    }
+
+    fn file_name(&self, module: &Module) -> String {
+        module_scala_name(module).plain
+    }
+
+    fn warnings(&self, hdl_info: &HdlInfo) -> Vec<String> {
+        scala_renames(hdl_info)
+    }
+
+    fn emit(&self, output: &Path, hdl_info: &HdlInfo) -> Result<(), Box<dyn Error>> {
+        self.layout.emit(self, output, hdl_info)
+    }
+}
+
+/// Split bundle variants go to a `split_bundle` directory below the target.
+fn split_bundle_directory(target: &str, split_bundle: bool) -> PathBuf {
+    if split_bundle { PathBuf::from(target).join("split_bundle") } else { PathBuf::from(target) }
+}
+
+pub(crate) fn write_to_file(path: &PathBuf, name: &str, contents: &str, suffix: &str) {
+    if let Err(e) = fs::create_dir_all(path) {
         eprintln!("Failed to create write file path [{}]: {}", path.display(), e);
     }
-    if let Err(e) = fs::write(path.join(format!("{}.{}", name, suffix)), contents) {
-        eprintln!("Failed to write {} to file [{}]: {}", name.to_string(), path.display(), e);
+    let file_name = if suffix.is_empty() { name.to_string() } else { format!("{}.{}", name, suffix) };
+    if let Err(e) = fs::write(path.join(file_name), contents) {
+        eprintln!("Failed to write {} to file [{}]: {}", name, path.display(), e);
     }
 }
 
@@ -61,39 +334,618 @@ fn indent_block(text: &str, indent_size: usize) -> String {
         .join("\n")
 }
 
-fn port_to_chisel(port: &Port) -> String {
-    format!("val {} = {}(UInt({}.W))", port.get_name(), port.get_direction(), port.get_width())
+/// `Defines` when the design has defines and an `object` per SV package
+/// that has parameters.
+fn scala_shared_files(hdl_info: &HdlInfo) -> Vec<(String, String)> {
+    let mut files = Vec::new();
+    if !hdl_info.get_defines().is_empty() {
+        files.push(("Defines".to_string(), defines_to_scala(hdl_info)));
+    }
+    hdl_info.get_packages().iter()
+        .filter(|p| !p.get_parameters().is_empty())
+        .for_each(|p| files.push((scala_name(&p.get_name()).plain, package_to_scala(p))));
+    files
 }
 
-fn module_to_chisel(module: &Module, split_bundle: bool) -> String {
-    let ports = module.get_ports().iter().map(|p| port_to_chisel(p)).collect::<Vec<String>>().join("\n");
-    let ports = indent_block(&ports, 8);
-    if split_bundle {
-        let bundle_name = module.get_name() + "_Bundle";
+/// `object <pkg>` with a `val` per numeric parameter. Other values are kept
+/// as comments.
+fn package_to_scala(package: &Package) -> String {
+    let lines = package.get_parameters().iter().map(|p| {
+        match scala_literal(&p.get_value()) {
+            Some(value) => format!("val {} = {}", scala_name(&p.get_name()).identifier, value),
+            None => format!("// {} = {}", p.get_name(), p.get_value()),
+        }
+    }).collect::<Vec<String>>().join("\n");
+
+    formatdoc! {"
+        object {} {{
+        {}
+        }}
+    ", scala_name(&package.get_name()).identifier, indent_block(&lines, 4)}
+}
+
+/// Scala literal of an evaluated define or parameter, if it is a number.
+fn scala_literal(value: &str) -> Option<String> {
+    if value.parse::<i32>().is_ok() {
+        Some(value.to_string())
+    } else if value.parse::<i64>().is_ok() {
+        Some(format!("{}L", value))
+    } else if value.parse::<BigInt>().is_ok() {
+        Some(format!("BigInt(\"{}\")", value))
+    } else if value.parse::<f64>().is_ok_and(f64::is_finite) {
+        Some(value.to_string())
+    } else {
+        None
+    }
+}
+
+/// `object Defines` with a `val` per numeric define. Macros without a numeric
+/// value, and repeated names from separately parsed files, are kept as comments
+/// with every line of their body commented out.
+fn defines_to_scala(hdl_info: &HdlInfo) -> String {
+    let mut names: Vec<String> = Vec::new();
+    let lines = hdl_info.get_defines().iter().map(|d| {
+        let value = d.get_evaluated().and_then(|v| scala_literal(&v));
+        let arguments = d.get_arguments().map(|a| format!("({})", a)).unwrap_or_default();
+        let comment = format!("`define {}{} {}", d.get_name(), arguments, d.get_value().trim()).lines()
+            .map(|l| format!("// {}", l).trim_end().to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+        let name = scala_name(&d.get_name());
+        match value {
+            Some(_) if names.contains(&name.plain) => format!("{} (already defined)", comment),
+            Some(value) => {
+                names.push(name.plain);
+                format!("val {} = {}", name.identifier, value)
+            }
+            None => comment,
+        }
+    }).collect::<Vec<String>>().join("\n");
+
+    formatdoc! {"
+        object Defines {{
+        {}
+        }}
+    ", indent_block(&lines, 4)}
+}
+
+/// Scala widths of the ports of a module, with the constructor parameters
+/// needed by those that could not be evaluated. `generics` are the ones that
+/// are module parameters, to be passed on to the RTL.
+struct ScalaPorts {
+    widths: Vec<String>,
+    parameters: Vec<String>,
+    generics: Vec<String>,
+}
+
+impl ScalaPorts {
+    /// Unresolved widths are translated from their range when it only uses
+    /// module parameters, or take a `<port>_width` parameter otherwise.
+    fn new(module: &Module, log2: &str) -> Self {
+        Self::with_parameters(module, log2, &[])
+    }
+
+    /// Like `new`, but widths depending on the module parameters `names`
+    /// refer to them by name, as the class takes them itself. Other module
+    /// parameters are substituted by their value.
+    fn with_parameters(module: &Module, log2: &str, names: &[String]) -> Self {
+        let values: HashMap<String, String> = module.get_parameters().iter()
+            .filter(|p| p.get_value().parse::<i32>().is_ok() && !names.contains(&p.get_name()))
+            .map(|p| (p.get_name(), p.get_value()))
+            .collect();
+        let overridable = module.get_parameters().iter().filter(|p| !p.is_local()).map(|p| p.get_name()).collect::<Vec<String>>();
+        let mut parameters: Vec<String> = Vec::new();
+        let widths = module.get_ports().iter().map(|port| {
+            let range = port.get_width_expression().unwrap_or_default();
+            if port.get_unresolved().is_none() {
+                if names.is_empty() {
+                    return port.get_width();
+                }
+                return match range_width(&range, &values, log2) {
+                    Some(w) if !w.parameters.is_empty() && w.parameters.iter().all(|p| names.contains(p)) => w.expression,
+                    _ => port.get_width(),
+                };
+            }
+            let (width, used) = match range_width(&range, &values, log2) {
+                Some(w) if w.parameters.iter().all(|p| overridable.contains(p)) => (w.expression, w.parameters),
+                _ => {
+                    let name = format!("{}_width", renamed_scala_name(&port.get_name(), port.get_scala_name()).plain);
+                    (name.clone(), vec![name])
+                }
+            };
+            used.into_iter()
+                .filter(|n| !names.contains(n))
+                .for_each(|n| if !parameters.contains(&n) { parameters.push(n) });
+            width
+        }).collect();
+        let generics = parameters.iter().filter(|p| overridable.contains(p)).cloned().collect();
+        ScalaPorts { widths, parameters, generics }
+    }
+
+    /// `(Map("A" -> IntParam(A)))` passing the generics to a Chisel
+    /// `BlackBox`, empty without generics.
+    fn chisel_params(&self) -> String {
+        if self.generics.is_empty() {
+            String::new()
+        } else {
+            format!("(Map({}))", self.generics.iter().map(|g| format!("\"{}\" -> IntParam({})", g, g)).collect::<Vec<String>>().join(", "))
+        }
+    }
+
+    /// `(A: Int, B: Int)` for the class declaration, empty without parameters.
+    fn declaration(&self) -> String {
+        if self.parameters.is_empty() {
+            String::new()
+        } else {
+            format!("({})", self.parameters.iter().map(|p| format!("{}: Int", p)).collect::<Vec<String>>().join(", "))
+        }
+    }
+
+    /// `(A, B)` to pass the parameters on, empty without parameters.
+    fn arguments(&self) -> String {
+        if self.parameters.is_empty() {
+            String::new()
+        } else {
+            format!("({})", self.parameters.join(", "))
+        }
+    }
+}
+
+/// Width expressions wider than a literal are parenthesized before `.W`.
+fn scala_width(width: &str) -> String {
+    if width.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        width.to_string()
+    } else {
+        format!("({})", width)
+    }
+}
+
+const SCALA_KEYWORDS: [&str; 44] = [
+    "abstract", "case", "catch", "class", "def", "do", "else", "enum", "export", "extends",
+    "false", "final", "finally", "for", "forSome", "given", "if", "implicit", "import", "lazy",
+    "macro", "match", "new", "null", "object", "override", "package", "private", "protected", "return",
+    "sealed", "super", "then", "this", "throw", "trait", "true", "try", "type", "val",
+    "var", "while", "with", "yield",
+];
+
+/// Scala name of an SV module or port. Reserved words are quoted with
+/// backticks in `identifier`, names that are no identifiers are `renamed`
+/// and have to keep their `verilog` name by other means.
+struct ScalaName {
+    identifier: String,
+    plain: String,
+    verilog: String,
+    renamed: bool,
+}
+
+fn scala_name(name: &str) -> ScalaName {
+    // Escaped identifiers `\name ` are `name` in the design
+    let verilog = name.strip_prefix('\\').unwrap_or(name).trim_end().to_string();
+    let mut plain = verilog.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect::<String>();
+    if plain.is_empty() || plain.starts_with(|c: char| c.is_ascii_digit()) {
+        plain.insert(0, '_');
+    }
+    let identifier = if SCALA_KEYWORDS.contains(&plain.as_str()) { format!("`{}`", plain) } else { plain.clone() };
+    let renamed = plain != verilog;
+    ScalaName { identifier, plain, verilog, renamed }
+}
+
+/// Scala name of the SV `name`, or of the `scala` name a transform gave it
+/// while the SV one is kept as `verilog`.
+fn renamed_scala_name(name: &str, scala: Option<String>) -> ScalaName {
+    let Some(scala) = scala else {
+        return scala_name(name);
+    };
+    let verilog = scala_name(name).verilog;
+    let name = scala_name(&scala);
+    ScalaName { renamed: name.plain != verilog, verilog, ..name }
+}
+
+fn module_scala_name(module: &Module) -> ScalaName {
+    renamed_scala_name(&module.get_name(), module.get_scala_name())
+}
+
+/// Scala names of the ports of `module`. A renamed port taking the name of
+/// another port gets a `_<n>` suffix.
+fn scala_port_names(module: &Module) -> Vec<ScalaName> {
+    let mut names = module.get_ports().iter().map(|p| renamed_scala_name(&p.get_name(), p.get_scala_name())).collect::<Vec<ScalaName>>();
+    for i in 0..names.len() {
+        if !names[i].renamed {
+            continue;
+        }
+        let base = names[i].plain.clone();
+        let mut suffix = 0;
+        while names.iter().enumerate().any(|(j, n)| j != i && n.plain == names[i].plain) {
+            suffix += 1;
+            names[i].plain = format!("{}_{}", base, suffix);
+            names[i].identifier = names[i].plain.clone();
+        }
+    }
+    names
+}
+
+/// Names changed for Scala. Modules and ports come with the SV name the
+/// generated code still binds to.
+fn scala_renames(hdl_info: &HdlInfo) -> Vec<String> {
+    let mut renames = Vec::new();
+    for define in hdl_info.get_defines() {
+        let name = scala_name(&define.get_name());
+        if name.renamed && define.get_evaluated().and_then(|v| scala_literal(&v)).is_some() {
+            renames.push(format!("define {} is named {} in Scala", define.get_name(), name.plain));
+        }
+    }
+    for package in hdl_info.get_packages().iter().filter(|p| !p.get_parameters().is_empty()) {
+        let name = scala_name(&package.get_name());
+        if name.renamed {
+            renames.push(format!("package {} is named {} in Scala", package.get_name(), name.plain));
+        }
+        package.get_parameters().iter()
+            .map(|p| (p, scala_name(&p.get_name())))
+            .filter(|(p, n)| n.renamed && scala_literal(&p.get_value()).is_some())
+            .for_each(|(p, n)| renames.push(format!("parameter {} of {} is named {} in Scala", p.get_name(), package.get_name(), n.plain)));
+    }
+    for module in hdl_info.get_modules() {
+        let name = module_scala_name(module);
+        if name.renamed {
+            renames.push(format!("module {} is named {} in Scala, bound to the RTL module {}", module.get_name(), name.plain, name.verilog));
+        }
+        module.get_ports().iter().zip(scala_port_names(module))
+            .filter(|(_, n)| n.renamed)
+            .for_each(|(p, n)| renames.push(format!("port {} of {} is named {} in Scala, bound to the RTL port {}", p.get_name(), module.get_name(), n.plain, n.verilog)));
+    }
+    renames
+}
+
+/// Ports in declaration order, except that the ports of a group follow its
+/// first port under a `// <group>` comment. Port annotations precede the port.
+fn ports_to_scala(module: &Module, widths: &[String], port_to_scala: fn(&Port, &ScalaName, &str) -> String) -> String {
+    let names = scala_port_names(module);
+    let ports = module.get_ports().iter().zip(&names).zip(widths).map(|((p, n), w)| (p, n, w)).collect::<Vec<(&Port, &ScalaName, &String)>>();
+    let port_lines = |(port, name, width): &(&Port, &ScalaName, &String)| {
+        let mut lines = port.get_annotations().clone();
+        lines.push(port_to_scala(port, name, width));
+        lines
+    };
+    let mut groups: Vec<String> = Vec::new();
+    let mut lines = Vec::new();
+    for port in &ports {
+        match port.0.get_group() {
+            None => lines.extend(port_lines(port)),
+            Some(group) if groups.contains(&group) => {}
+            Some(group) => {
+                lines.push(format!("// {}", group));
+                ports.iter()
+                    .filter(|(p, _, _)| p.get_group().as_ref() == Some(&group))
+                    .for_each(|p| lines.extend(port_lines(p)));
+                groups.push(group);
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+/// Module annotations, one per line, to put before the class.
+fn annotations_to_scala(module: &Module) -> String {
+    module.get_annotations().iter().map(|a| format!("{}\n", a)).collect()
+}
+
+/// `UInt`, or the type a transform chose. `UInt`, `SInt` and `Bits` take the
+/// port width, other names are constructed without arguments.
+fn scala_port_type(port: &Port, width: &str, width_unit: fn(&str) -> String) -> String {
+    match port.get_scala_type() {
+        None => format!("UInt({})", width_unit(width)),
+        Some(t) if ["UInt", "SInt", "Bits"].contains(&t.as_str()) => format!("{}({})", t, width_unit(width)),
+        Some(t) if t.contains('(') => t,
+        Some(t) => format!("{}()", t),
+    }
+}
+
+/// Scala string literal of `text`.
+fn scala_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Path of the RTL source of `module` as given to the parser, made absolute
+/// when the file exists so that it does not depend on the build directory.
+fn rtl_path(module: &Module) -> Option<String> {
+    let source = module.get_source()?;
+    let path = fs::canonicalize(&source).map_or(source, |p| p.to_string_lossy().to_string());
+    Some(path)
+}
+
+/// Trait mixed into the Chisel class and the line of the class body adding
+/// the RTL source, both empty without a source.
+fn chisel_rtl_source(module: &Module, converter: &ChiselConverter) -> (String, String) {
+    let (Some(rtl_source), Some(path)) = (converter.rtl_source, rtl_path(module)) else {
+        return (String::new(), String::new());
+    };
+    let kind = if converter.ext_module { "ExtModule" } else { "BlackBox" };
+    match rtl_source {
+        RtlSource::Resource => {
+            let name = Path::new(&path).file_name().map_or(path.clone(), |n| n.to_string_lossy().to_string());
+            (format!(" with Has{}Resource", kind), format!("    addResource({})\n", scala_string(&format!("/{}", name))))
+        }
+        RtlSource::Path => (format!(" with Has{}Path", kind), format!("    addPath({})\n", scala_string(&path))),
+    }
+}
+
+/// Clock domains of a BlackBox with a copy of `module` declaring their clocks
+/// as `clock_type` and their resets as `Bool`. Domains with a port whose type
+/// a transform changed to something else are left out.
+fn typed_clock_domains(module: &Module, clock_type: &str) -> (Vec<ClockDomain>, Module) {
+    let has_type = |name: &str, scala_type: &str| module.get_ports().iter()
+        .any(|p| p.get_name() == name && p.get_scala_type().is_none_or(|t| t == scala_type));
+    let domains = clock_domains(module).into_iter()
+        .filter(|d| has_type(&d.get_clock(), clock_type) && d.get_reset().is_none_or(|r| has_type(&r, "Bool")))
+        .collect::<Vec<ClockDomain>>();
+    let mut module = module.clone();
+    for domain in &domains {
+        module.set_port_type(&domain.get_clock(), clock_type);
+        if let Some(reset) = domain.get_reset() {
+            module.set_port_type(&reset, "Bool");
+        }
+    }
+    (domains, module)
+}
+
+/// `Input(..)`/`Output(..)` of the port type, or an `Analog` for an inout.
+fn chisel_port_type(port: &Port, width: &str) -> String {
+    if port.get_direction() == "inout" && port.get_scala_type().is_none() {
+        return format!("Analog({}.W)", scala_width(width));
+    }
+    let port_type = scala_port_type(port, width, |w| format!("{}.W", scala_width(w)));
+    format!("{}({})", chisel_direction(&port.get_direction()), port_type)
+}
+
+/// Bundle fields are named by the field; `chisel_record` keeps the SV name
+/// of renamed ports.
+fn port_to_chisel(port: &Port, name: &ScalaName, width: &str) -> String {
+    format!("val {} = {}", name.identifier, chisel_port_type(port, width))
+}
+
+/// `<name>_Bundle` `Record` with the port fields `ports`, naming each port of
+/// the `BlackBox` by its SV name through `elements`. `None` when no port is
+/// renamed, as a `Bundle` names them by the fields.
+fn chisel_record(module: &Module, name: &str, ports: &str, declaration: &str, arguments: &str) -> Option<String> {
+    let names = scala_port_names(module);
+    if !names.iter().any(|n| n.renamed) {
+        return None;
+    }
+    let elements = names.iter()
+        .map(|n| format!("{} -> {}", scala_string(&n.verilog), n.identifier))
+        .collect::<Vec<String>>()
+        .join(",\n");
+    Some(formatdoc! {"
+        class {}_Bundle{} extends Record {{
+        {}
+            val elements = ListMap(
+        {}
+            )
+            override def cloneType: this.type = (new {}_Bundle{}).asInstanceOf[this.type]
+        }}
+    ", name, declaration, indent_block(ports, 4), indent_block(&elements, 8), name, arguments})
+}
+
+/// `desiredName` keeping the SV name of a renamed module, empty otherwise.
+fn chisel_desired_name(name: &ScalaName) -> String {
+    if name.renamed { format!("    override def desiredName = {}\n", scala_string(&name.verilog)) } else { String::new() }
+}
+
+/// `Analog` when a port is an inout without a type set by a transform.
+fn chisel_analog(module: &Module) -> Option<&'static str> {
+    module.get_ports().iter()
+        .any(|p| p.get_direction() == "inout" && p.get_scala_type().is_none())
+        .then_some("Analog")
+}
+
+/// `<name>_Wrapper` module instantiating the BlackBox or ExtModule of `module`
+/// as `inner`, with the clock and reset of the first domain driven by the
+/// implicit ones (inverted for an active low reset) and the other ports
+/// passed through under their names. Empty without a clock domain; otherwise
+/// the experimental members it uses come with it.
+fn chisel_wrapper(module: &Module, domains: &[ClockDomain], widths: &[String], declaration: &str, arguments: &str, ext_module: bool) -> (String, Vec<&'static str>) {
+    let Some(domain) = domains.first() else {
+        return (String::new(), Vec::new());
+    };
+    let names = module.get_ports().iter().map(|p| p.get_name()).zip(scala_port_names(module)).collect::<HashMap<String, ScalaName>>();
+    let inner = |port: &String| format!("inner.{}{}", if ext_module { "" } else { "io." }, names[port].identifier);
+    let mut lines = vec![format!("{} := clock", inner(&domain.get_clock()))];
+    let mut wrapper = module.clone();
+    wrapper.remove_port(&domain.get_clock());
+    if let Some(reset) = domain.get_reset() {
+        let inversion = if domain.is_reset_active_low() { "!" } else { "" };
+        lines.push(format!("{} := {}reset.asBool", inner(&reset), inversion));
+        wrapper.remove_port(&reset);
+    }
+
+    let wrapper_names = wrapper.get_ports().iter().map(|p| p.get_name()).zip(scala_port_names(&wrapper)).collect::<HashMap<String, ScalaName>>();
+    let mut experimental = vec!["FlatIO"];
+    for port in wrapper.get_ports() {
+        let (outer, inner) = (format!("io.{}", wrapper_names[&port.get_name()].identifier), inner(&port.get_name()));
+        match port.get_direction().as_str() {
+            "input" => lines.push(format!("{} := {}", inner, outer)),
+            "output" => lines.push(format!("{} := {}", outer, inner)),
+            _ => {
+                lines.push(format!("attach({}, {})", outer, inner));
+                if !experimental.contains(&"attach") {
+                    experimental.push("attach");
+                }
+            }
+        }
+    }
+    let widths = module.get_ports().iter().zip(widths)
+        .filter(|(p, _)| wrapper_names.contains_key(&p.get_name()))
+        .map(|(_, w)| w.clone())
+        .collect::<Vec<String>>();
+    let ports = indent_block(&ports_to_scala(&wrapper, &widths, port_to_chisel), 8);
+    let name = module_scala_name(module);
+    let code = formatdoc! {"
+
+        class {}_Wrapper{} extends Module {{
+            val io = FlatIO(new Bundle {{
+        {}
+            }})
+            val inner = Module(new {}{})
+        {}
+        }}
+    ", name.plain, declaration, ports, name.identifier, arguments, indent_block(&lines.join("\n"), 4)};
+    (code, experimental)
+}
+
+fn module_to_chisel(module: &Module, converter: &ChiselConverter) -> String {
+    // Clock ports are declared as Clock to be driven by the wrapper
+    let (domains, module) = typed_clock_domains(module, "Clock");
+    let module = &module;
+    let scala_ports = ScalaPorts::new(module, "log2Ceil");
+    let port_lines = ports_to_scala(module, &scala_ports.widths, port_to_chisel);
+    let ports = indent_block(&port_lines, 8);
+    let (mixin, rtl_source) = chisel_rtl_source(module, converter);
+    let (declaration, arguments) = (scala_ports.declaration(), scala_ports.arguments());
+    let (wrapper, mut experimental) = chisel_wrapper(module, &domains, &scala_ports.widths, &declaration, &arguments, false);
+    experimental.extend(chisel_analog(module));
+    if !scala_ports.generics.is_empty() {
+        experimental.push("IntParam");
+    }
+    experimental.sort();
+    let util = !mixin.is_empty() || scala_ports.widths.iter().any(|w| w.contains("log2Ceil("));
+    let imports = converter.version.imports(&experimental, util);
+    let mixin = format!("{}{}", scala_ports.chisel_params(), mixin);
+    let annotations = annotations_to_scala(module);
+    let name = module_scala_name(module);
+    let body = format!("{}{}", chisel_desired_name(&name), rtl_source);
+    if let Some(record) = chisel_record(module, &name.plain, &port_lines, &declaration, &arguments) {
         formatdoc! {"
-            import chisel3._
+            {}
+            import scala.collection.immutable.ListMap
 
-            class {} extends Bundle {{
             {}
-            }}
+            {}class {}{} extends BlackBox{} {{
+                val io = IO(new {}_Bundle{})
+            {}}}
+            {}", imports, record, annotations, name.identifier, declaration, mixin, name.plain, arguments, body, wrapper}
+    } else if converter.split_bundle {
+        let bundle_name = name.plain.clone() + "_Bundle";
+        formatdoc! {"
+            {}
 
-            class {} extends BlackBox {{
-                val io = IO(new {})
+            class {}{} extends Bundle {{
+            {}
             }}
-        ", bundle_name, ports, module.get_name(), bundle_name}
+
+            {}class {}{} extends BlackBox{} {{
+                val io = IO(new {}{})
+            {}}}
+            {}", imports, bundle_name, declaration, ports, annotations, name.identifier, declaration, mixin, bundle_name, arguments, body, wrapper}
     } else {
         formatdoc! {"
-            import chisel3._
+            {}
 
-            class {} extends BlackBox {{
+            {}class {}{} extends BlackBox{} {{
                 val io = IO(new Bundle {{
             {}
                 }})
-            }}
-        ", module.get_name(), ports}
+            {}}}
+            {}", imports, annotations, name.identifier, declaration, mixin, ports, body, wrapper}
+    }
+}
+
+/// Module parameter of an `ExtModule`: the constructor argument with the SV
+/// default, if the value has a Scala type, and the entry of its `Param` map.
+struct ExtModuleParameter {
+    declaration: Option<String>,
+    param: &'static str,
+    entry: String,
+}
+
+fn ext_module_parameter(name: &str, value: &str) -> ExtModuleParameter {
+    let typed = |scala_type: &str, default: String, param: &'static str| ExtModuleParameter {
+        declaration: Some(format!("{}: {} = {}", name, scala_type, default)),
+        param,
+        entry: format!("\"{}\" -> {}({})", name, param, name),
+    };
+    if value.parse::<i32>().is_ok() {
+        typed("Int", value.to_string(), "IntParam")
+    } else if value.parse::<BigInt>().is_ok() {
+        typed("BigInt", format!("BigInt(\"{}\")", value), "IntParam")
+    } else if value.parse::<f64>().is_ok_and(f64::is_finite) {
+        typed("Double", value.to_string(), "DoubleParam")
+    } else if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        typed("String", value.to_string(), "StringParam")
+    } else {
+        ExtModuleParameter { declaration: None, param: "RawParam", entry: format!("\"{}\" -> RawParam({})", name, scala_string(value)) }
+    }
+}
+
+fn ext_module_int_parameter(name: &str) -> ExtModuleParameter {
+    ExtModuleParameter {
+        declaration: Some(format!("{}: Int", name)),
+        param: "IntParam",
+        entry: format!("\"{}\" -> IntParam({})", name, name),
     }
 }
 
+fn chisel_direction(direction: &str) -> &str {
+    match direction {
+        "input" => "Input",
+        "output" => "Output",
+        _ => direction,
+    }
+}
+
+fn port_to_chisel_ext_module(port: &Port, name: &ScalaName, width: &str) -> String {
+    let suggest_name = if name.renamed { format!(".suggestName({})", scala_string(&name.verilog)) } else { String::new() };
+    format!("val {} = IO({}){}", name.identifier, chisel_port_type(port, width), suggest_name)
+}
+
+/// `ExtModule` whose non-local parameters are constructor arguments passed
+/// on as `Map[String, Param]`. Widths depending on `Int` parameters follow them.
+fn module_to_chisel_ext_module(module: &Module, converter: &ChiselConverter) -> String {
+    let (domains, module) = typed_clock_domains(module, "Clock");
+    let module = &module;
+    let int_names = module.get_parameters().iter()
+        .filter(|p| !p.is_local() && p.get_value().parse::<i32>().is_ok())
+        .map(|p| p.get_name())
+        .collect::<Vec<String>>();
+    let scala_ports = ScalaPorts::with_parameters(module, "log2Ceil", &int_names);
+    // Parameters without a value that widths depend on are taken as Int
+    let parameters = module.get_parameters().iter()
+        .filter(|p| !p.is_local())
+        .map(|p| match scala_ports.generics.contains(&p.get_name()) {
+            true => (p.get_name(), ext_module_int_parameter(&p.get_name())),
+            false => (p.get_name(), ext_module_parameter(&p.get_name(), &p.get_value())),
+        })
+        .collect::<Vec<(String, ExtModuleParameter)>>();
+    let ports = indent_block(&ports_to_scala(module, &scala_ports.widths, port_to_chisel_ext_module), 4);
+    let declarations = scala_ports.parameters.iter()
+        .filter(|p| !scala_ports.generics.contains(p))
+        .map(|p| format!("{}: Int", p))
+        .chain(parameters.iter().filter_map(|(_, p)| p.declaration.clone()))
+        .collect::<Vec<String>>();
+    let declaration = if declarations.is_empty() { String::new() } else { format!("({})", declarations.join(", ")) };
+    let arguments = declarations.iter().filter_map(|d| d.split(':').next()).collect::<Vec<&str>>();
+    let arguments = if arguments.is_empty() { String::new() } else { format!("({})", arguments.join(", ")) };
+    let (wrapper, mut experimental) = chisel_wrapper(module, &domains, &scala_ports.widths, &declaration, &arguments, true);
+
+    experimental.push("ExtModule");
+    experimental.extend(chisel_analog(module));
+    parameters.iter().for_each(|(_, p)| if !experimental.contains(&p.param) { experimental.push(p.param) });
+    experimental.sort();
+    let (mixin, rtl_source) = chisel_rtl_source(module, converter);
+    let util = !mixin.is_empty() || scala_ports.widths.iter().any(|w| w.contains("log2Ceil("));
+    let imports = converter.version.imports(&experimental, util);
+
+    let entries = parameters.iter().map(|(_, p)| p.entry.clone()).collect::<Vec<String>>();
+    let params = if entries.is_empty() { String::new() } else { format!("(Map({}))", entries.join(", ")) };
+    let name = module_scala_name(module);
+    formatdoc! {"
+        {}
+
+        {}class {}{} extends ExtModule{}{} {{
+        {}
+        {}}}
+        {}", imports, annotations_to_scala(module), name.identifier, declaration, params, mixin, ports, format!("{}{}", chisel_desired_name(&name), rtl_source), wrapper}
+}
+
 fn spinal_map_direction(direction: &str) -> &str {
     match direction {
         "input" => "in port",
@@ -102,37 +954,474 @@ fn spinal_map_direction(direction: &str) -> &str {
     }
 }
 
-fn port_to_spinal(port: &Port) -> String {
-    format!("val {} = {} UInt({} bits)", port.get_name(), spinal_map_direction(port.get_direction().as_str()), port.get_width())
+fn port_to_spinal(port: &Port, name: &ScalaName, width: &str) -> String {
+    let port_type = scala_port_type(port, width, |w| format!("{} bits", scala_width(w)));
+    let port = format!("{} {}", spinal_map_direction(port.get_direction().as_str()), port_type);
+    if name.renamed {
+        format!("val {} = ({}).setName({})", name.identifier, port, scala_string(&name.verilog))
+    } else {
+        format!("val {} = {}", name.identifier, port)
+    }
+}
+
+/// Constructor parameters and class body lines mapping the clock domains.
+/// A single domain is the current one, several are passed to the class.
+fn spinal_map_clock_domains(module: &Module, domains: &[ClockDomain]) -> (Vec<String>, String) {
+    let names = module.get_ports().iter().map(|p| p.get_name()).zip(scala_port_names(module)).collect::<HashMap<String, ScalaName>>();
+    let signal = |port: &String| format!("io.{}", names[port].identifier);
+    let mut parameters = Vec::new();
+    let mut lines = String::new();
+    for domain in domains {
+        let mut arguments = vec![format!("clock = {}", signal(&domain.get_clock()))];
+        if let Some(reset) = domain.get_reset() {
+            arguments.push(format!("reset = {}", signal(&reset)));
+            if domain.is_reset_active_low() {
+                arguments.push("resetActiveLevel = LOW".to_string());
+            }
+        }
+        if domains.len() == 1 {
+            lines += &format!("    mapCurrentClockDomain({})\n", arguments.join(", "));
+        } else {
+            let parameter = format!("{}_domain", names[&domain.get_clock()].plain);
+            lines += &format!("    mapClockDomain({}, {})\n", parameter, arguments.join(", "));
+            parameters.push(format!("{}: ClockDomain", parameter));
+        }
+    }
+    (parameters, lines)
 }
 
-fn module_to_spinal(module: &Module, split_bundle: bool) -> String {
-    let ports = module.get_ports().iter().map(|p| port_to_spinal(p)).collect::<Vec<String>>().join("\n");
+fn module_to_spinal(module: &Module, converter: &SpinalConverter) -> String {
+    // Clock and reset ports are mapped as Bool
+    let (domains, module) = typed_clock_domains(module, "Bool");
+    let module = &module;
+
+    let scala_ports = ScalaPorts::new(module, "log2Up");
+    let ports = ports_to_scala(module, &scala_ports.widths, port_to_spinal);
     let ports = indent_block(&ports, 8);
-    if split_bundle {
-        let bundle_name = module.get_name() + "_Bundle";
+    let (domain_parameters, map_clock_domains) = spinal_map_clock_domains(module, &domains);
+    let (declaration, arguments) = (scala_ports.declaration(), scala_ports.arguments());
+    let class_parameters = scala_ports.parameters.iter().map(|p| format!("{}: Int", p)).chain(domain_parameters).collect::<Vec<String>>();
+    let class_declaration = if class_parameters.is_empty() { String::new() } else { format!("({})", class_parameters.join(", ")) };
+    let annotations = annotations_to_scala(module);
+    let rtl_source = match rtl_path(module) {
+        Some(path) if converter.rtl_path => format!("    addRTLPath({})\n", scala_string(&path)),
+        _ => String::new(),
+    };
+    let name = module_scala_name(module);
+    let definition_name = if name.renamed { format!("    setDefinitionName({})\n", scala_string(&name.verilog)) } else { String::new() };
+    let generics = scala_ports.generics.iter().map(|g| format!("    addGeneric({}, {})\n", scala_string(g), g)).collect::<String>();
+    let body = format!("{}{}{}{}", definition_name, generics, map_clock_domains, rtl_source);
+    if converter.split_bundle {
+        let bundle_name = name.plain.clone() + "_Bundle";
         formatdoc! {"
             import spinal.core._
             import spinal.lib._
 
-            class {} extends Bundle {{
+            class {}{} extends Bundle {{
             {}
             }}
 
-            class {} extends BlackBox {{
-                val io = new {}
-            }}
-        ", bundle_name, ports, module.get_name(), bundle_name}
+            {}class {}{} extends BlackBox {{
+                val io = new {}{}
+            {}}}
+        ", bundle_name, declaration, ports, annotations, name.identifier, class_declaration, bundle_name, arguments, body}
     } else {
         formatdoc! {"
             import spinal.core._
             import spinal.lib._
 
-            class {} extends BlackBox {{
+            {}class {}{} extends BlackBox {{
                 val io = new Bundle {{
             {}
                 }}
-            }}
-        ", module.get_name(), ports}
+            {}}}
+        ", annotations, name.identifier, class_declaration, ports, body}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sv_parse::parse_file;
+
+    #[test]
+    fn convert_defines_to_scala() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("define_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+
+        // 可求值的宏生成 val，带参数的宏连同形参保留为注释，多行宏逐行注释
+        assert_eq!(defines_to_scala(&hdl_info), indoc::indoc! {"
+            object Defines {
+                val DEPTH = 32
+                val WIDTH = 16
+                val ADDR_W = 5
+                // `define MAX(a, b) ((a) > (b) ? (a) : (b))
+                // `define CLAMP(v, hi) \\
+                //     ((v) > (hi) ? \\
+                //      (hi) : (v))
+            }
+        "});
+    }
+
+    #[test]
+    fn convert_packages_to_scala() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("package_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let packages = hdl_info.get_packages().iter().map(package_to_scala).collect::<Vec<String>>();
+
+        // 包参数按声明类型求值，import 的参数可直接引用
+        assert_eq!(packages, [
+            indoc::indoc! {"
+                object cfg_pkg {
+                    val DATA_W = 32
+                    val STRB_W = 4
+                    // MODE = 4'b10x1
+                    val FREQ = 100.0
+                }
+            "},
+            indoc::indoc! {"
+                object bus_cfg_pkg {
+                    val ADDR_W = 24
+                    val BEAT_W = 36
+                }
+            "},
+        ]);
+
+        // 模块通过 import 和 pkg:: 使用包参数
+        let module = &hdl_info.get_modules()[0];
+        let widths = module.get_ports().iter().map(|p| p.get_width()).collect::<Vec<String>>();
+        assert_eq!(widths, ["32", "4", "3"]);
+    }
+
+    #[test]
+    fn convert_transformed_ports() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let mut hdl_info = parse_file(&root.join("sv").join("hierarchy_top.sv"), &HashMap::new()).expect("parse_file failed");
+        let transform = crate::transform::Transform::from_file(&root.join("transform").join("conventions.rhai")).expect("from_file failed");
+        transform.apply(&mut hdl_info).expect("apply failed");
+        // 同组端口集中输出，注解和类型覆盖原样生成，改名后仍绑定 SV 名字
+        assert_eq!(module_to_spinal(&hdl_info.get_modules()[1], &SpinalConverter::builder()), indoc::indoc! {"
+            import spinal.core._
+            import spinal.lib._
+
+            // generated by conventions.rhai
+            class HierarchyTop extends BlackBox {
+                val io = new Bundle {
+                    val clk = in port Clock()
+                    // inputs
+                    // sampled on clk
+                    val data = (in port UInt(4 bits)).setName(\"d\")
+                    val q0 = out port UInt(4 bits)
+                    val q1 = out port UInt(4 bits)
+                }
+                setDefinitionName(\"hierarchy_top\")
+            }
+        "});
+
+        // 改名的时钟仍与 always 的边沿事件对应
+        let mut module = hdl_info.get_modules()[1].clone();
+        module.rename_port("clk", "clock_in");
+        let scala = module_to_chisel_ext_module(&module, &ChiselConverter::builder().ext_module());
+        assert!(scala.contains("    val clock_in = IO(Input(Clock())).suggestName(\"clk\")\n"));
+        assert!(scala.contains("    override def desiredName = \"hierarchy_top\"\n"));
+        assert!(scala.contains("    inner.clock_in := clock\n"));
+        let scala = module_to_chisel(&module, &ChiselConverter::builder());
+        assert!(scala.contains("        \"clk\" -> clock_in,\n"));
+        assert!(scala.contains("    inner.io.clock_in := clock\n"));
+    }
+
+    #[test]
+    fn convert_chisel_ext_module() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv");
+        let hdl_info = parse_file(&root.join("generate_module.sv"), &HashMap::new()).expect("parse_file failed");
+        // 参数通过 Map[String, Param] 传递，依赖参数的位宽随参数变化
+        let module = hdl_info.get_module("generate_module").unwrap();
+        assert_eq!(module_to_chisel_ext_module(module, &ChiselConverter::builder()), indoc::indoc! {r#"
+            import chisel3._
+            import chisel3.experimental.{ExtModule, IntParam}
+
+            class generate_module(LANES: Int = 3, USE_SYNC: Int = 1, MODE: Int = 2) extends ExtModule(Map("LANES" -> IntParam(LANES), "USE_SYNC" -> IntParam(USE_SYNC), "MODE" -> IntParam(MODE))) {
+                val clk = IO(Input(Clock()))
+                val d = IO(Input(UInt((LANES * 8).W)))
+                val q = IO(Output(UInt((LANES * 8).W)))
+            }
+
+            class generate_module_Wrapper(LANES: Int = 3, USE_SYNC: Int = 1, MODE: Int = 2) extends Module {
+                val io = FlatIO(new Bundle {
+                    val d = Input(UInt((LANES * 8).W))
+                    val q = Output(UInt((LANES * 8).W))
+                })
+                val inner = Module(new generate_module(LANES, USE_SYNC, MODE))
+                inner.clk := clock
+                inner.d := io.d
+                io.q := inner.q
+            }
+        "#});
+
+        // 超出 Int 的参数用 BigInt，决定端口宽度但无法求值的参数作为 Int 传递
+        let hdl_info = parse_file(&root.join("wide_module.sv"), &HashMap::new()).expect("parse_file failed");
+        let scala = module_to_chisel_ext_module(&hdl_info.get_modules()[0], &ChiselConverter::builder());
+        assert!(scala.contains(r#"SEED: BigInt = BigInt("18446744073709551616")"#));
+        assert!(scala.contains(r#""UNKNOWN" -> IntParam(UNKNOWN)"#));
+        assert!(scala.contains("val flags = IO(Output(UInt((UNKNOWN + 1).W)))"));
+        assert!(scala.contains("val seed = IO(Input(UInt(WIDE_BITS.W)))"));
+    }
+
+    #[test]
+    fn convert_unresolved_widths() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("unresolved_module.sv");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let module = &hdl_info.get_modules()[0];
+
+        // 模块参数作为构造参数并传给 RTL，其它名字改用 <port>_width
+        assert_eq!(module_to_chisel(module, &ChiselConverter::builder()), indoc::indoc! {"
+            import chisel3._
+            import chisel3.experimental.IntParam
+
+            class unresolved_module(a_width: Int, b_width: Int, d_width: Int, e_width: Int, DW: Int, g_width: Int) extends BlackBox(Map(\"DW\" -> IntParam(DW))) {
+                val io = IO(new Bundle {
+                    val a = Input(UInt(a_width.W))
+                    val b = Output(UInt(b_width.W))
+                    val c = Input(UInt(4.W))
+                    val d = Input(UInt(d_width.W))
+                    val e = Input(UInt(e_width.W))
+                    val f = Output(UInt(DW.W))
+                    val g = Input(UInt(g_width.W))
+                })
+            }
+        "});
+        let scala = module_to_chisel_ext_module(module, &ChiselConverter::builder().ext_module());
+        assert!(scala.contains("e_width: Int, g_width: Int, DW: Int) extends ExtModule(Map(\"DW\" -> IntParam(DW))) {"));
+        let scala = module_to_spinal(module, &SpinalConverter::builder());
+        assert!(scala.contains("    addGeneric(\"DW\", DW)\n}"));
+        // 宏在解析时已展开，未声明的名字不会成为构造参数
+        assert!(!scala.contains("UNDEF_P") && !scala.contains("Defines."));
+    }
+
+    #[test]
+    fn target_chisel_versions() {
+        assert_eq!("3.6".parse::<ChiselVersion>(), Ok(ChiselVersion::Chisel3));
+        assert_eq!("7".parse::<ChiselVersion>(), Ok(ChiselVersion::Chisel5));
+        assert!("4".parse::<ChiselVersion>().is_err());
+
+        // 3.x 的 FlatIO 位于 experimental，之后的版本按名字导入
+        assert_eq!(ChiselVersion::Chisel3.imports(&["FlatIO"], false), "import chisel3._\nimport chisel3.experimental._");
+        assert_eq!(ChiselVersion::Chisel5.imports(&["FlatIO"], true), "import chisel3._\nimport chisel3.util._");
+        assert_eq!(ChiselVersion::Chisel5.imports(&["Analog", "ExtModule"], false), "import chisel3._\nimport chisel3.experimental.{Analog, ExtModule}");
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("module_with_inout.sv");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let scala = module_to_chisel(&hdl_info.get_modules()[0], &ChiselConverter::builder().version(ChiselVersion::Chisel3));
+        assert!(scala.contains("val a = Input(UInt(1.W))"));
+        assert!(scala.contains("val pad = Analog(1.W)"));
+    }
+
+    #[test]
+    fn attach_rtl_source() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("module_with_inout.sv");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let module = &hdl_info.get_modules()[0];
+        assert_eq!(module.get_source(), Some(path.to_string_lossy().to_string()));
+
+        // 资源按文件名引用，路径使用源文件的绝对路径
+        let chisel = ChiselConverter::builder().rtl_source(RtlSource::Resource);
+        let scala = module_to_chisel(module, &chisel);
+        assert!(scala.contains("class module_with_inout extends BlackBox with HasBlackBoxResource {"));
+        assert!(scala.contains("    addResource(\"/module_with_inout.sv\")\n}"));
+        let scala = module_to_chisel_ext_module(module, &ChiselConverter::builder().ext_module().rtl_source(RtlSource::Path));
+        assert!(scala.contains("extends ExtModule with HasExtModulePath {"));
+        assert!(scala.contains(&format!("    addPath({})\n}}", scala_string(&path.to_string_lossy()))));
+
+        let scala = module_to_spinal(module, &SpinalConverter::builder().rtl_path());
+        assert!(scala.contains(&format!("    addRTLPath({})\n}}", scala_string(&path.to_string_lossy()))));
+        assert!(!module_to_spinal(module, &SpinalConverter::builder()).contains("addRTLPath"));
+    }
+
+    #[test]
+    fn layout_scala_files() {
+        let layout = ScalaLayout::builder().package("com.acme.ip");
+        assert_eq!(layout.directory(PathBuf::from("chisel")), PathBuf::from("chisel/com/acme/ip"));
+
+        // 合并文件时 import 去重并放在 package 之后
+        let sources = [
+            "object Defines {\n}\n".to_string(),
+            "import chisel3._\n\nclass a extends BlackBox {\n}\n".to_string(),
+            "package com.acme.ip\n\nimport chisel3._\nimport chisel3.util._\n\nclass b extends BlackBox {\n}\n".to_string(),
+        ];
+        assert_eq!(layout.file(&sources), indoc::indoc! {"
+            package com.acme.ip
+
+            import chisel3._
+            import chisel3.util._
+
+            object Defines {
+            }
+
+            class a extends BlackBox {
+            }
+
+            class b extends BlackBox {
+            }
+        "});
+
+        // 没有 package 时单个文件保持不变
+        assert_eq!(ScalaLayout::builder().file(&sources[1..2]), sources[1]);
+    }
+
+    #[test]
+    fn escape_scala_names() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("reserved_module.sv");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        // 保留字加反引号，非法标识符改名并报告
+        assert_eq!(scala_renames(&hdl_info), [
+            "define end$ is named end_ in Scala",
+            "package \\pkg-a is named pkg_a in Scala",
+            "parameter w$ of \\pkg-a is named w_ in Scala",
+            "port \\bus[0] of object is named bus_0__1 in Scala, bound to the RTL port bus[0]",
+            "module \\ip-core is named ip_core in Scala, bound to the RTL module ip-core",
+        ]);
+
+        // 宏、包和包参数的名字同样转义
+        assert_eq!(defines_to_scala(&hdl_info), indoc::indoc! {"
+            object Defines {
+                val `val` = 3
+                val end_ = 5
+            }
+        "});
+        assert_eq!(scala_shared_files(&hdl_info)[1].0, "pkg_a");
+        assert_eq!(package_to_scala(&hdl_info.get_packages()[0]), indoc::indoc! {"
+            object pkg_a {
+                val `def` = 4
+                val w_ = 2
+            }
+        "});
+
+        let chisel = ChiselConverter::builder().ext_module();
+        assert_eq!(chisel.file_name(&hdl_info.get_modules()[1]), "ip_core");
+        assert_eq!(module_to_chisel_ext_module(&hdl_info.get_modules()[0], &chisel), indoc::indoc! {r#"
+            import chisel3._
+            import chisel3.experimental.ExtModule
+
+            class `object` extends ExtModule {
+                val `val` = IO(Input(UInt(1.W)))
+                val bus_0__1 = IO(Input(UInt(4.W))).suggestName("bus[0]")
+                val bus_0_ = IO(Input(UInt(4.W)))
+                val `match` = IO(Output(UInt(1.W)))
+                val `type` = IO(Output(UInt(1.W)))
+            }
+        "#});
+        // BlackBox 的端口名取自 io 的 elements，改名的端口用 Record 保留 SV 名字
+        assert_eq!(module_to_chisel(&hdl_info.get_modules()[0], &ChiselConverter::builder()), indoc::indoc! {r#"
+            import chisel3._
+            import scala.collection.immutable.ListMap
+
+            class object_Bundle extends Record {
+                val `val` = Input(UInt(1.W))
+                val bus_0__1 = Input(UInt(4.W))
+                val bus_0_ = Input(UInt(4.W))
+                val `match` = Output(UInt(1.W))
+                val `type` = Output(UInt(1.W))
+                val elements = ListMap(
+                    "val" -> `val`,
+                    "bus[0]" -> bus_0__1,
+                    "bus_0_" -> bus_0_,
+                    "match" -> `match`,
+                    "type" -> `type`
+                )
+                override def cloneType: this.type = (new object_Bundle).asInstanceOf[this.type]
+            }
+
+            class `object` extends BlackBox {
+                val io = IO(new object_Bundle)
+            }
+        "#});
+        let split = module_to_chisel(&hdl_info.get_modules()[0], &ChiselConverter::builder().split_bundle());
+        assert!(split.contains("class object_Bundle extends Record {"));
+        let scala = module_to_chisel(&hdl_info.get_modules()[1], &chisel);
+        assert!(scala.contains("class ip_core extends BlackBox {"));
+        assert!(scala.contains("    override def desiredName = \"ip-core\"\n}"));
+
+        let scala = module_to_spinal(&hdl_info.get_modules()[0], &SpinalConverter::builder());
+        assert!(scala.contains("val bus_0__1 = (in port UInt(4 bits)).setName(\"bus[0]\")"));
+    }
+
+    #[test]
+    fn map_spinal_clock_domains() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("clock_domain_module.sv");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let spinal = SpinalConverter::builder();
+        // 单个时钟域映射到当前时钟域
+        let scala = module_to_spinal(hdl_info.get_module("async_reset_module").unwrap(), &spinal);
+        assert!(scala.contains("val clk = in port Bool()"));
+        assert!(scala.contains("    mapCurrentClockDomain(clock = io.clk, reset = io.rst_n, resetActiveLevel = LOW)\n}"));
+
+        // 多个时钟域作为构造参数传入
+        assert_eq!(module_to_spinal(hdl_info.get_module("dual_clock_stub").unwrap(), &spinal), indoc::indoc! {"
+            import spinal.core._
+            import spinal.lib._
+
+            class dual_clock_stub(clk_a_i_domain: ClockDomain, clk_io_domain: ClockDomain) extends BlackBox {
+                val io = new Bundle {
+                    val clk_a_i = in port Bool()
+                    val rst_a_ni = in port Bool()
+                    val clk_en = in port UInt(1 bits)
+                    val clk_io = in port Bool()
+                    val rst_io = in port Bool()
+                    val data_a = in port UInt(8 bits)
+                    val data_b = out port UInt(8 bits)
+                }
+                mapClockDomain(clk_a_i_domain, clock = io.clk_a_i, reset = io.rst_a_ni, resetActiveLevel = LOW)
+                mapClockDomain(clk_io_domain, clock = io.clk_io, reset = io.rst_io)
+            }
+        "});
+    }
+
+    #[test]
+    fn wrap_chisel_clock_domains() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("clock_domain_module.sv");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let chisel = ChiselConverter::builder();
+        // 低有效复位取反后连接到隐式复位
+        assert_eq!(module_to_chisel(hdl_info.get_module("async_reset_module").unwrap(), &chisel), indoc::indoc! {"
+            import chisel3._
+
+            class async_reset_module extends BlackBox {
+                val io = IO(new Bundle {
+                    val clk = Input(Clock())
+                    val rst_n = Input(Bool())
+                    val d = Input(UInt(1.W))
+                    val q = Output(UInt(1.W))
+                })
+            }
+
+            class async_reset_module_Wrapper extends Module {
+                val io = FlatIO(new Bundle {
+                    val d = Input(UInt(1.W))
+                    val q = Output(UInt(1.W))
+                })
+                val inner = Module(new async_reset_module)
+                inner.io.clk := clock
+                inner.io.rst_n := !reset.asBool
+                inner.io.d := io.d
+                io.q := inner.io.q
+            }
+        "});
+
+        // 第一个时钟域使用隐式时钟，其余时钟域的端口原样引出
+        let scala = module_to_chisel_ext_module(hdl_info.get_module("dual_clock_stub").unwrap(), &chisel.clone().ext_module());
+        assert!(scala.contains("    inner.clk_a_i := clock\n    inner.rst_a_ni := !reset.asBool\n"));
+        assert!(scala.contains("        val clk_io = Input(Clock())\n        val rst_io = Input(Bool())\n"));
+        assert!(scala.contains("    inner.rst_io := io.rst_io\n"));
+
+        // 没有时钟的模块不生成包装
+        assert!(!module_to_chisel(hdl_info.get_module("comb_module").unwrap(), &chisel).contains("_Wrapper"));
     }
 }
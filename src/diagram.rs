@@ -0,0 +1,223 @@
+use std::{collections::{BTreeMap, HashMap}, path::PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::converter::write_to_file;
+use crate::hdl_info::HierarchyNode;
+
+#[derive(Default)]
+pub struct DiagramEmitter {
+    max_depth: Option<usize>,
+    collapse_leaves: bool
+}
+
+/// Flattened block diagram shared by the DOT and Mermaid writers. Nodes are
+/// numbered in the order they are added, and labels are lists of lines.
+#[derive(Default)]
+struct Diagram {
+    nodes: Vec<(String, Vec<String>)>,
+    tree_edges: Vec<(String, String)>,
+    net_edges: Vec<(String, String, String)>,
+}
+
+impl Diagram {
+    fn add_node(&mut self, label: Vec<String>) -> String {
+        let id = format!("n{}", self.nodes.len());
+        self.nodes.push((id.clone(), label));
+        id
+    }
+}
+
+impl DiagramEmitter {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    /// Only draw instances up to `depth` levels below the top module.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Draw leaf instances of the same module under one parent as a single block.
+    pub fn collapse_leaves(mut self) -> Self {
+        self.collapse_leaves = true;
+        self
+    }
+
+    pub fn emit_dot_string(&self, hierarchy: &HierarchyNode) -> String {
+        diagram_to_dot(&self.build_diagram(hierarchy))
+    }
+
+    pub fn emit_mermaid_string(&self, hierarchy: &HierarchyNode) -> String {
+        diagram_to_mermaid(&self.build_diagram(hierarchy))
+    }
+
+    pub fn emit(&self, path: &PathBuf, hierarchy: &HierarchyNode) {
+        write_to_file(path, "hierarchy", &self.emit_dot_string(hierarchy), "dot");
+        write_to_file(path, "hierarchy", &self.emit_mermaid_string(hierarchy), "mmd");
+    }
+
+    fn build_diagram(&self, hierarchy: &HierarchyNode) -> Diagram {
+        let mut diagram = Diagram::default();
+        let id = diagram.add_node(vec![hierarchy.get_module_name()]);
+        self.add_children(&mut diagram, &id, hierarchy, 1);
+        diagram
+    }
+
+    fn add_children(&self, diagram: &mut Diagram, parent_id: &str, node: &HierarchyNode, depth: usize) {
+        if self.max_depth.is_some_and(|max| depth > max) {
+            return;
+        }
+
+        // Block id of every child, shared by collapsed leaves of the same module
+        let mut child_ids = Vec::new();
+        let mut collapsed: BTreeMap<String, usize> = BTreeMap::new();
+        for child in node.get_children() {
+            let is_leaf = child.get_children().is_empty();
+            if self.collapse_leaves && is_leaf {
+                *collapsed.entry(child.get_module_name()).or_default() += 1;
+                child_ids.push(None);
+            } else {
+                let id = diagram.add_node(vec![child.get_instance_name(), child.get_module_name()]);
+                diagram.tree_edges.push((parent_id.to_string(), id.clone()));
+                self.add_children(diagram, &id, child, depth + 1);
+                child_ids.push(Some(id));
+            }
+        }
+        let mut collapsed_ids = HashMap::new();
+        for (module_name, count) in collapsed {
+            let id = diagram.add_node(vec![format!("{} x{}", module_name, count)]);
+            diagram.tree_edges.push((parent_id.to_string(), id.clone()));
+            collapsed_ids.insert(module_name, id);
+        }
+        let child_ids = node.get_children().iter().zip(child_ids)
+            .map(|(child, id)| id.unwrap_or_else(|| collapsed_ids[&child.get_module_name()].clone()))
+            .collect::<Vec<String>>();
+
+        // Signals of the parent connected to more than one child block
+        let mut nets: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (child, id) in node.get_children().iter().zip(&child_ids) {
+            for c in child.get_connections() {
+                for signal in expression_signals(&c.get_expression().unwrap_or_default()) {
+                    let ids = nets.entry(signal).or_default();
+                    if !ids.contains(id) {
+                        ids.push(id.clone());
+                    }
+                }
+            }
+        }
+        for (signal, ids) in nets {
+            for id in ids.iter().skip(1) {
+                diagram.net_edges.push((ids[0].clone(), id.clone(), signal.clone()));
+            }
+        }
+    }
+}
+
+/// Identifiers referenced by a port connection expression, skipping the base
+/// and digits of sized literals such as `4'b0`.
+fn expression_signals(expression: &str) -> Vec<String> {
+    lazy_static! {
+        static ref SIGNAL_RE: Regex = Regex::new(r"(^|[^'\w$])([A-Za-z_][\w$]*)").unwrap();
+    }
+
+    SIGNAL_RE.captures_iter(expression).map(|c| c[2].to_string()).collect()
+}
+
+/// DOT string contents: quotes and backslashes are escaped, lines are joined
+/// with `\n`.
+fn dot_label(lines: &[String]) -> String {
+    lines.iter().map(|l| l.replace('\\', "\\\\").replace('"', "\\\"")).collect::<Vec<String>>().join("\\n")
+}
+
+/// Mermaid quoted label: characters taken as markup become entity codes,
+/// lines are joined with `<br/>`.
+fn mermaid_label(lines: &[String]) -> String {
+    let escape = |line: &String| line.replace('#', "#35;").replace('"', "#quot;").replace('<', "#lt;").replace('>', "#gt;");
+    lines.iter().map(escape).collect::<Vec<String>>().join("<br/>")
+}
+
+fn diagram_to_dot(diagram: &Diagram) -> String {
+    let mut lines = vec!["digraph hierarchy {".to_string(), "    node [shape=box];".to_string()];
+    for (id, label) in &diagram.nodes {
+        lines.push(format!("    {} [label=\"{}\"];", id, dot_label(label)));
+    }
+    for (from, to) in &diagram.tree_edges {
+        lines.push(format!("    {} -> {};", from, to));
+    }
+    for (from, to, signal) in &diagram.net_edges {
+        lines.push(format!("    {} -> {} [label=\"{}\", style=dashed, dir=none, constraint=false];", from, to, dot_label(std::slice::from_ref(signal))));
+    }
+    lines.push("}".to_string());
+    lines.join("\n") + "\n"
+}
+
+fn diagram_to_mermaid(diagram: &Diagram) -> String {
+    let mut lines = vec!["flowchart TD".to_string()];
+    for (id, label) in &diagram.nodes {
+        lines.push(format!("    {}[\"{}\"]", id, mermaid_label(label)));
+    }
+    for (from, to) in &diagram.tree_edges {
+        lines.push(format!("    {} --> {}", from, to));
+    }
+    for (from, to, signal) in &diagram.net_edges {
+        lines.push(format!("    {} -. \"{}\" .- {}", from, mermaid_label(std::slice::from_ref(signal)), to));
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sv_parse::parse_file;
+
+    fn hierarchy_top() -> HierarchyNode {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("hierarchy_top.sv");
+
+        parse_file(&path, &HashMap::new()).expect("parse_file failed").hierarchy("hierarchy_top").unwrap()
+    }
+
+    #[test]
+    fn emit_hierarchy_dot() {
+        let dot = DiagramEmitter::builder().emit_dot_string(&hierarchy_top());
+
+        assert!(dot.contains("n2 [label=\"u_leaf\\nhierarchy_leaf\"];"));
+        assert!(dot.contains("n0 -> n4;"));
+        // 兄弟实例间共享的信号画成虚线
+        assert!(dot.contains("n1 -> n4 [label=\"clk\""));
+        assert!(dot.contains("n1 -> n4 [label=\"d\""));
+    }
+
+    #[test]
+    fn emit_escaped_hierarchy() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv").join("escaped_hierarchy.sv");
+        let hierarchy = parse_file(&path, &HashMap::new()).expect("parse_file failed").hierarchy("\\escaped\"top").unwrap();
+
+        // 标签中的引号和反斜杠被转义
+        let dot = DiagramEmitter::builder().emit_dot_string(&hierarchy);
+        assert!(dot.contains("n0 [label=\"\\\\escaped\\\"top\"];"));
+        assert!(dot.contains("n4 [label=\"\\\\u\\\"q<1>\\nescaped_leaf\"];"));
+        // a.b 与 a__b 是不同的节点
+        assert!(dot.contains("n1 -> n2;\n    n0 -> n3;"));
+
+        let mermaid = DiagramEmitter::builder().emit_mermaid_string(&hierarchy);
+        assert!(mermaid.contains("n4[\"\\u#quot;q#lt;1#gt;<br/>escaped_leaf\"]"));
+    }
+
+    #[test]
+    fn emit_hierarchy_mermaid_with_options() {
+        let mermaid = DiagramEmitter::builder().max_depth(1).emit_mermaid_string(&hierarchy_top());
+        assert!(mermaid.contains("n1[\"u_mid0<br/>hierarchy_mid\"]"));
+        assert!(!mermaid.contains("u_leaf"));
+
+        let mermaid = DiagramEmitter::builder().collapse_leaves().emit_mermaid_string(&hierarchy_top());
+        assert!(mermaid.contains("n3[\"vendor_sync_cell x1\"]\n"));
+        assert!(mermaid.contains("n1 --> n3\n"));
+        assert!(!mermaid.contains("u_sync"));
+    }
+}
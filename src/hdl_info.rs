@@ -1,3 +1,5 @@
+use std::fmt;
+
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,24 +9,139 @@ pub struct Port {
     port_type: String,
     width: String,
     width_expression: Option<String>,
+    unresolved: Option<Unresolved>,
+    /// Scala type replacing `UInt`, set by a transform script.
+    #[serde(default)]
+    scala_type: Option<String>,
+    /// Scala name given by a transform script, `name` stays the SV one.
+    #[serde(default)]
+    scala_name: Option<String>,
+    /// Ports of the same group are generated together.
+    #[serde(default)]
+    group: Option<String>,
+    /// Lines generated verbatim before the port.
+    #[serde(default)]
+    annotations: Vec<String>,
+}
+
+/// Location of a port width that could not be evaluated, in which case the
+/// width is the range text. `identifier` is the name that failed to resolve,
+/// `reason` why evaluation failed otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Unresolved {
+    #[serde(default)]
+    source: Option<String>,
+    line: u32,
+    identifier: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
 }
 
+/// Text macro in effect at the end of the file. `value` is the macro body and
+/// `evaluated` its constant value, when it has one.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Define {
     name: String,
-    value: String
+    #[serde(default)]
+    arguments: Option<String>,
+    value: String,
+    evaluated: Option<String>,
+}
+
+/// Warning found while extracting, with the file and line it refers to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    #[serde(default)]
+    source: Option<String>,
+    line: u32,
+    message: String,
+}
+
+/// Module `parameter`/`localparam`. `value` is the evaluated default, or the
+/// expression itself when it could not be evaluated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parameter {
+    name: String,
+    value: String,
+    expression: String,
+    local: bool,
+}
+
+/// Parameter override of an instance, `#(.NAME(value))` or `#(value)`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterOverride {
+    name: Option<String>,
+    value: String,
+}
+
+/// Port connection of an instance. Ordered connections have no `port`, and
+/// unconnected ports (`.a()`) have no `expression`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Connection {
+    port: Option<String>,
+    expression: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instance {
+    name: String,
+    module_type: String,
+    parameters: Vec<ParameterOverride>,
+    connections: Vec<Connection>,
+}
+
+/// Edge event of an `always` event control, `posedge clk`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeEvent {
+    signal: String,
+    edge: String,
+}
+
+/// SV package with its `parameter`/`localparam` constants.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Package {
+    name: String,
+    parameters: Vec<Parameter>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Module {
     name: String,
-    ports: Vec<Port>
+    parameters: Vec<Parameter>,
+    ports: Vec<Port>,
+    instances: Vec<Instance>,
+    /// File the module was parsed from, which may be an included file.
+    #[serde(default)]
+    source: Option<String>,
+    /// Scala name given by a transform script, `name` stays the SV one.
+    #[serde(default)]
+    scala_name: Option<String>,
+    /// Edge events of each distinct `always` event control.
+    #[serde(default)]
+    edge_events: Vec<Vec<EdgeEvent>>,
+    /// Lines generated verbatim before the class.
+    #[serde(default)]
+    annotations: Vec<String>,
+}
+
+/// Node of the elaborated design tree rooted at the top module.
+#[derive(Debug, Serialize)]
+pub struct HierarchyNode {
+    instance_name: String,
+    module_name: String,
+    parameters: Vec<ParameterOverride>,
+    connections: Vec<Connection>,
+    children: Vec<HierarchyNode>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HdlInfo {
     defines: Vec<Define>,
-    modules: Vec<Module>
+    #[serde(default)]
+    packages: Vec<Package>,
+    modules: Vec<Module>,
+    #[serde(default)]
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Port {
@@ -39,6 +156,144 @@ impl Port {
     pub fn get_width(&self) -> String {
         self.width.clone()
     }
+
+    pub fn get_width_expression(&self) -> Option<String> {
+        self.width_expression.clone()
+    }
+
+    pub fn get_unresolved(&self) -> Option<Unresolved> {
+        self.unresolved.clone()
+    }
+
+    pub fn get_port_type(&self) -> String {
+        self.port_type.clone()
+    }
+
+    pub fn get_scala_type(&self) -> Option<String> {
+        self.scala_type.clone()
+    }
+
+    pub fn get_scala_name(&self) -> Option<String> {
+        self.scala_name.clone()
+    }
+
+    pub fn get_group(&self) -> Option<String> {
+        self.group.clone()
+    }
+
+    pub fn get_annotations(&self) -> &Vec<String> {
+        &self.annotations
+    }
+}
+
+impl Unresolved {
+    pub fn new(source: Option<String>, line: u32, identifier: Option<String>, reason: Option<String>) -> Self {
+        Unresolved { source, line, identifier, reason }
+    }
+
+    pub fn get_source(&self) -> Option<String> {
+        self.source.clone()
+    }
+
+    pub fn get_line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn get_identifier(&self) -> Option<String> {
+        self.identifier.clone()
+    }
+
+    pub fn get_reason(&self) -> Option<String> {
+        self.reason.clone()
+    }
+}
+
+impl Define {
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_value(&self) -> String {
+        self.value.clone()
+    }
+
+    /// Formal arguments of a macro with arguments, `a, b` for `MAX(a, b)`.
+    pub fn get_arguments(&self) -> Option<String> {
+        self.arguments.clone()
+    }
+
+    pub fn get_evaluated(&self) -> Option<String> {
+        self.evaluated.clone()
+    }
+}
+
+impl Diagnostic {
+    pub fn get_source(&self) -> Option<String> {
+        self.source.clone()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}:{}: {}", source, self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl ParameterOverride {
+    pub fn new(name: Option<&str>, value: &str) -> Self {
+        ParameterOverride { name: name.map(str::to_string), value: value.to_string() }
+    }
+}
+
+impl Connection {
+    pub fn new(port: Option<&str>, expression: Option<&str>) -> Self {
+        Connection { port: port.map(str::to_string), expression: expression.map(str::to_string) }
+    }
+
+    pub fn get_expression(&self) -> Option<String> {
+        self.expression.clone()
+    }
+}
+
+impl EdgeEvent {
+    pub fn new(signal: &str, edge: &str) -> Self {
+        EdgeEvent { signal: signal.to_string(), edge: edge.to_string() }
+    }
+
+    pub fn get_signal(&self) -> String {
+        self.signal.clone()
+    }
+
+    pub fn get_edge(&self) -> String {
+        self.edge.clone()
+    }
+}
+
+impl Parameter {
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_value(&self) -> String {
+        self.value.clone()
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+}
+
+impl Package {
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_parameters(&self) -> &Vec<Parameter> {
+        &self.parameters
+    }
 }
 
 impl Module {
@@ -46,45 +301,345 @@ impl Module {
         &self.ports
     }
 
+    pub fn get_parameters(&self) -> &Vec<Parameter> {
+        &self.parameters
+    }
+
     pub fn get_name(&self) -> String {
         self.name.clone()
     }
+
+    pub fn get_annotations(&self) -> &Vec<String> {
+        &self.annotations
+    }
+
+    pub fn get_source(&self) -> Option<String> {
+        self.source.clone()
+    }
+
+    pub fn get_scala_name(&self) -> Option<String> {
+        self.scala_name.clone()
+    }
+
+    pub fn get_edge_events(&self) -> &Vec<Vec<EdgeEvent>> {
+        &self.edge_events
+    }
+
+    pub fn set_scala_name(&mut self, name: &str) {
+        self.scala_name = Some(name.to_string());
+    }
+
+    pub fn add_annotation(&mut self, annotation: &str) {
+        self.annotations.push(annotation.to_string());
+    }
+
+    /// Give port `from` the Scala name `to`, returning whether it exists.
+    pub fn rename_port(&mut self, from: &str, to: &str) -> bool {
+        self.update_port(from, |p| p.scala_name = Some(to.to_string()))
+    }
+
+    pub fn set_port_type(&mut self, name: &str, scala_type: &str) -> bool {
+        self.update_port(name, |p| p.scala_type = Some(scala_type.to_string()))
+    }
+
+    pub fn set_port_group(&mut self, name: &str, group: &str) -> bool {
+        self.update_port(name, |p| p.group = Some(group.to_string()))
+    }
+
+    pub fn add_port_annotation(&mut self, name: &str, annotation: &str) -> bool {
+        self.update_port(name, |p| p.annotations.push(annotation.to_string()))
+    }
+
+    pub fn remove_port(&mut self, name: &str) -> bool {
+        let count = self.ports.len();
+        self.ports.retain(|p| p.name != name);
+        self.ports.len() != count
+    }
+
+    fn update_port(&mut self, name: &str, update: impl FnOnce(&mut Port)) -> bool {
+        match self.ports.iter_mut().find(|p| p.name == name) {
+            Some(port) => {
+                update(port);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 impl HdlInfo {
     pub fn new() -> Self {
         HdlInfo {
             defines: Vec::new(),
-            modules: Vec::new()
+            packages: Vec::new(),
+            modules: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
+    pub fn add_package(&mut self, name: &str) {
+        self.packages.push(Package { name: name.to_string(), parameters: Vec::new() });
+    }
+
+    pub fn add_package_parameter(&mut self, name: &str, value: &str, expression: &str, local: bool) {
+        if let Some(last_package) = self.packages.last_mut() {
+            last_package.parameters.push(Parameter {
+                name: name.to_string(),
+                value: value.to_string(),
+                expression: expression.to_string(),
+                local
+            });
+        }
+    }
+
+    pub fn get_packages(&self) -> &Vec<Package> {
+        &self.packages
+    }
+
     pub fn add_module(&mut self, name: &str) {
-        self.modules.push(Module { name: name.to_string(), ports: Vec::new() });
+        self.modules.push(Module { name: name.to_string(), parameters: Vec::new(), ports: Vec::new(), instances: Vec::new(), source: None, scala_name: None, edge_events: Vec::new(), annotations: Vec::new() });
+    }
+
+    /// Record a define. A redefinition replaces the earlier body in place.
+    pub fn add_define(&mut self, name: &str, arguments: Option<&str>, value: &str) {
+        let arguments = arguments.map(str::to_string);
+        match self.defines.iter_mut().find(|d| d.name == name) {
+            Some(define) => {
+                define.arguments = arguments;
+                define.value = value.to_string();
+                define.evaluated = None;
+            }
+            None => self.defines.push(Define { name: name.to_string(), arguments, value: value.to_string(), evaluated: None }),
+        }
+    }
+
+    pub fn remove_define(&mut self, name: &str) {
+        self.defines.retain(|d| d.name != name);
+    }
+
+    pub fn set_define_value(&mut self, name: &str, evaluated: &str) {
+        if let Some(define) = self.defines.iter_mut().find(|d| d.name == name) {
+            define.evaluated = Some(evaluated.to_string());
+        }
+    }
+
+    pub fn get_define(&self, name: &str) -> Option<&Define> {
+        self.defines.iter().find(|d| d.name == name)
     }
 
-    pub fn add_define(&mut self, name: &str, value: &str) {
-        self.defines.push(Define { name: name.to_string(), value: value.to_string() });
+    pub fn get_defines(&self) -> &Vec<Define> {
+        &self.defines
     }
 
-    pub fn add_ports(&mut self, name: &str, direction: &str, port_type: &str, width: &str, width_expression: &Option<String>) {
+    pub fn add_diagnostic(&mut self, source: Option<String>, line: u32, message: &str) {
+        self.diagnostics.push(Diagnostic { source, line, message: message.to_string() });
+    }
+
+    pub fn get_diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
+
+    pub fn add_ports(&mut self, name: &str, direction: &str, port_type: &str, width: &str, width_expression: &Option<String>, unresolved: &Option<Unresolved>) {
         if let Some(last_module) = self.modules.last_mut() {
             last_module.ports.push(Port {
                 name: name.to_string(),
                 direction: direction.to_string(),
                 port_type: port_type.to_string(),
                 width: width.to_string(),
-                width_expression: width_expression.clone()
+                width_expression: width_expression.clone(),
+                unresolved: unresolved.clone(),
+                scala_type: None,
+                scala_name: None,
+                group: None,
+                annotations: Vec::new()
             });
         }
     }
 
+    pub fn set_module_source(&mut self, source: &str) {
+        if let Some(last_module) = self.modules.last_mut() {
+            last_module.source = Some(source.to_string());
+        }
+    }
+
+    pub fn add_parameter(&mut self, name: &str, value: &str, expression: &str, local: bool) {
+        if let Some(last_module) = self.modules.last_mut() {
+            last_module.parameters.push(Parameter {
+                name: name.to_string(),
+                value: value.to_string(),
+                expression: expression.to_string(),
+                local
+            });
+        }
+    }
+
+    pub fn add_instance(&mut self, name: &str, module_type: &str, parameters: Vec<ParameterOverride>, connections: Vec<Connection>) {
+        if let Some(last_module) = self.modules.last_mut() {
+            last_module.instances.push(Instance {
+                name: name.to_string(),
+                module_type: module_type.to_string(),
+                parameters,
+                connections
+            });
+        }
+    }
+
+    pub fn add_edge_events(&mut self, events: Vec<EdgeEvent>) {
+        if let Some(last_module) = self.modules.last_mut()
+            && !events.is_empty()
+            && !last_module.edge_events.contains(&events)
+        {
+            last_module.edge_events.push(events);
+        }
+    }
+
+    /// Merge a separate net/variable declaration (`reg [7:0] b;`) into the
+    /// matching non-ANSI port of the last module. Implicitly typed ports take
+    /// the declared type, and ports without a range take the declared width.
+    pub fn merge_port_declaration(&mut self, name: &str, port_type: &str, width: &str, width_expression: &Option<String>, unresolved: &Option<Unresolved>) {
+        let Some(last_module) = self.modules.last_mut() else {
+            return;
+        };
+        if let Some(port) = last_module.ports.iter_mut().find(|p| p.name == name) {
+            if port.port_type == "wire" {
+                port.port_type = port_type.to_string();
+            }
+            if port.width_expression.is_none() && width_expression.is_some() {
+                port.width = width.to_string();
+                port.width_expression = width_expression.clone();
+                port.unresolved = unresolved.clone();
+            }
+        }
+    }
+
+    /// Sort the ports of the last module by their position in the module
+    /// header. Ports missing from `order` keep their relative order at the end.
+    pub fn reorder_ports(&mut self, order: &[String]) {
+        if let Some(last_module) = self.modules.last_mut() {
+            last_module.ports.sort_by_key(|p| order.iter().position(|n| *n == p.name).unwrap_or(order.len()));
+        }
+    }
+
     pub fn get_modules(&self) -> &Vec<Module> {
         &self.modules
     }
 
+    pub fn set_modules(&mut self, modules: Vec<Module>) {
+        self.modules = modules;
+    }
+
+    pub fn get_module(&self, name: &str) -> Option<&Module> {
+        self.modules.iter().find(|m| m.name == name)
+    }
+
+    /// Modules that are not instantiated by any other module.
+    pub fn top_modules(&self) -> Vec<String> {
+        self.modules.iter()
+            .filter(|m| !self.modules.iter().any(|p| p.instances.iter().any(|i| i.module_type == m.name)))
+            .map(|m| m.name.clone())
+            .collect()
+    }
+
+    /// Elaborate the instance tree below `top`. Instances of modules that were
+    /// not extracted are leaves; recursive instantiation stops at the cycle.
+    pub fn hierarchy(&self, top: &str) -> Option<HierarchyNode> {
+        let module = self.get_module(top)?;
+        let mut stack = vec![top.to_string()];
+        Some(HierarchyNode {
+            instance_name: top.to_string(),
+            module_name: top.to_string(),
+            parameters: Vec::new(),
+            connections: Vec::new(),
+            children: self.elaborate_instances(module, &mut stack),
+        })
+    }
+
+    fn elaborate_instances(&self, module: &Module, stack: &mut Vec<String>) -> Vec<HierarchyNode> {
+        module.instances.iter().map(|i| {
+            let children = match self.get_module(&i.module_type) {
+                Some(child) if !stack.contains(&i.module_type) => {
+                    stack.push(i.module_type.clone());
+                    let children = self.elaborate_instances(child, stack);
+                    stack.pop();
+                    children
+                }
+                _ => Vec::new(),
+            };
+            HierarchyNode {
+                instance_name: i.name.clone(),
+                module_name: i.module_type.clone(),
+                parameters: i.parameters.clone(),
+                connections: self.elaborate_connections(i),
+                children,
+            }
+        }).collect()
+    }
+
+    /// Expand a `.*` connection into one implicit connection per port of the
+    /// instantiated module that is not connected explicitly.
+    fn elaborate_connections(&self, instance: &Instance) -> Vec<Connection> {
+        let mut connections = Vec::new();
+        for c in &instance.connections {
+            match (c.port.as_deref(), self.get_module(&instance.module_type)) {
+                (Some("*"), Some(module)) => {
+                    module.ports.iter()
+                        .filter(|p| !instance.connections.iter().any(|c| c.port.as_ref() == Some(&p.name)))
+                        .for_each(|p| connections.push(Connection::new(Some(&p.name), Some(&p.name))));
+                }
+                _ => connections.push(c.clone()),
+            }
+        }
+        connections
+    }
+
+    /// Merge a file parsed as a compilation unit of its own. Defines that all
+    /// files get from the command line or filelist are kept once.
     pub fn merge_info(&mut self, info: &HdlInfo) {
-        info.defines.iter().for_each(|d| self.defines.push(d.clone()));
+        for define in &info.defines {
+            if !self.defines.iter().any(|d| d.name == define.name && d.value == define.value) {
+                self.defines.push(define.clone());
+            }
+        }
+        info.packages.iter().for_each(|p| self.packages.push(p.clone()));
+        info.modules.iter().for_each(|m| self.modules.push(m.clone()));
+        info.diagnostics.iter().for_each(|d| self.diagnostics.push(d.clone()));
+    }
+
+    /// Merge a file of the same compilation unit. Its defines already include
+    /// those of the files before it, so they replace the current ones.
+    pub fn merge_unit_info(&mut self, info: &HdlInfo) {
+        self.defines = info.defines.clone();
+        info.packages.iter().for_each(|p| self.packages.push(p.clone()));
         info.modules.iter().for_each(|m| self.modules.push(m.clone()));
+        info.diagnostics.iter().for_each(|d| self.diagnostics.push(d.clone()));
+    }
+}
+
+impl HierarchyNode {
+    pub fn get_instance_name(&self) -> String {
+        self.instance_name.clone()
+    }
+
+    pub fn get_module_name(&self) -> String {
+        self.module_name.clone()
+    }
+
+    pub fn get_connections(&self) -> &Vec<Connection> {
+        &self.connections
+    }
+
+    pub fn get_children(&self) -> &Vec<HierarchyNode> {
+        &self.children
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        writeln!(f, "{}{} ({})", "  ".repeat(depth), self.instance_name, self.module_name)?;
+        self.children.iter().try_for_each(|c| c.fmt_indented(f, depth + 1))
+    }
+}
+
+impl fmt::Display for HierarchyNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
     }
 }
@@ -1,38 +1,176 @@
-use std::{fs::{self, File}, path::PathBuf};
+use std::{collections::HashMap, fs::{self, File}, path::{Path, PathBuf}};
 use clap::Parser;
 
 mod hdl_info;
 mod sv_parse;
 mod converter;
+mod backend;
+mod clock_domain;
+mod template_backend;
+mod diagram;
+mod const_eval;
+mod scala_expr;
+mod transform;
 
-use crate::converter::ChiselConverter;
+use crate::backend::{Backend, BackendRegistry};
+use crate::converter::{ChiselConverter, ChiselVersion, RtlSource, ScalaLayout, SpinalConverter};
+use crate::template_backend::TemplateBackend;
+use crate::diagram::DiagramEmitter;
+use crate::sv_parse::CompilationUnit;
+use crate::transform::Transform;
 
 #[derive(Parser)]
 struct Args {
-    #[arg(short = 'i', long = "input")]
-    input: String,
-    #[arg(short = 'o', long = "output")]
-    output: String,
+    #[arg(short = 'i', long = "input", required_unless_present_any = ["filelist", "list_backends"])]
+    input: Option<String>,
+    /// Read source files, `+incdir+` and `+define+` options from a filelist, in order
+    #[arg(short = 'f', long = "filelist")]
+    filelist: Option<String>,
+    /// Parse all files as one compilation unit, so defines carry over from file to file
+    #[arg(long = "mfcu")]
+    mfcu: bool,
+    /// Fail instead of generating code when a port width cannot be evaluated
+    #[arg(long = "strict")]
+    strict: bool,
+    /// Run the `transform(m)` function of this rhai script on every module before generation
+    #[arg(long = "transform")]
+    transform: Option<String>,
+    /// Generate code with these backends (repeatable), all registered ones by default
+    #[arg(short = 'b', long = "backend")]
+    backends: Vec<String>,
+    /// Chisel release the Chisel backends target: 3 (3.5/3.6), or 5, 6 and 7
+    #[arg(long = "chisel-version", default_value = "6")]
+    chisel_version: ChiselVersion,
+    /// Point generated BlackBoxes at the RTL file of each module: `resource` adds it
+    /// by file name with HasBlackBoxResource, `path` by path with HasBlackBoxPath.
+    /// SpinalHDL BlackBoxes get `addRTLPath` with either
+    #[arg(long = "rtl-source")]
+    rtl_source: Option<RtlSource>,
+    /// Put the Scala code into this package, in the matching directories
+    #[arg(long = "scala-package")]
+    scala_package: Option<String>,
+    /// Write all Scala classes of a backend into one file named after the package
+    #[arg(long = "single-file")]
+    single_file: bool,
+    /// Register a template backend from this directory of `.j2` templates (repeatable)
+    #[arg(short = 't', long = "template")]
+    templates: Vec<String>,
+    /// Print the registered backends and exit
+    #[arg(long = "list-backends")]
+    list_backends: bool,
+    #[arg(short = 'o', long = "output", required_unless_present = "list_backends")]
+    output: Option<String>,
+    /// Override a module parameter as NAME=VALUE when evaluating widths and generate blocks
+    #[arg(short = 'P', long = "param", value_parser = parse_parameter)]
+    params: Vec<(String, String)>,
+    /// Report the elaborated instance tree below this module
+    #[arg(long = "top")]
+    top: Option<String>,
+    /// Limit the hierarchy diagrams to this many levels below the top module
+    #[arg(long = "diagram-depth")]
+    diagram_depth: Option<usize>,
+    /// Draw leaf instances of the same module as a single block in the diagrams
+    #[arg(long = "collapse-leaves")]
+    collapse_leaves: bool,
+}
+
+fn parse_parameter(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| format!("Expected NAME=VALUE, got {}", s))
+}
+
+fn report_diagnostics(path: &Path, info: &hdl_info::HdlInfo) {
+    for diagnostic in info.get_diagnostics() {
+        match diagnostic.get_source() {
+            Some(_) => eprintln!("Warning: {}", diagnostic),
+            None => eprintln!("Warning: {}: {}", path.display(), diagnostic),
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    let params: HashMap<String, String> = args.params.iter().cloned().collect();
+
+    let mut layout = ScalaLayout::builder();
+    if let Some(package) = &args.scala_package {
+        layout = layout.package(package);
+    }
+    if args.single_file {
+        layout = layout.single_file();
+    }
+    let mut chisel = ChiselConverter::builder().version(args.chisel_version).layout(layout.clone());
+    let mut spinal = SpinalConverter::builder().layout(layout);
+    if let Some(rtl_source) = args.rtl_source {
+        chisel = chisel.rtl_source(rtl_source);
+        spinal = spinal.rtl_path();
+    }
+    let mut registry = BackendRegistry::with_converters(chisel, spinal);
+    for dir in &args.templates {
+        registry = registry.register(TemplateBackend::from_dir(Path::new(dir))?);
+    }
+    if args.list_backends {
+        for backend in registry.get_backends() {
+            println!("{}\t*.{}\t{}", backend.name(), backend.file_extension(), backend.directory().display());
+        }
+        return Ok(());
+    }
+    let backends = if args.backends.is_empty() {
+        registry.get_backends().iter().map(|b| b.as_ref()).collect()
+    } else {
+        args.backends.iter()
+            .map(|name| registry.get(name).ok_or_else(|| format!("Unknown backend {}, see --list-backends", name)))
+            .collect::<Result<Vec<&dyn Backend>, String>>()?
+    };
 
-    let input = PathBuf::from(args.input);
-    let output = PathBuf::from(args.output);
+    let output = PathBuf::from(args.output.unwrap_or_default());
 
     let mut hdl_info = hdl_info::HdlInfo::new();
 
-    if input.is_dir() {
-        let files = fs::read_dir(input).unwrap();
-        for file in files {
-            let file = file.unwrap();
-            let info = sv_parse::parse_file(&file.path()).unwrap();
+    let mut files = Vec::new();
+    let mut unit = CompilationUnit::builder();
+    if let Some(filelist) = &args.filelist {
+        let info;
+        (files, unit, info) = sv_parse::read_filelist(Path::new(filelist))?;
+        report_diagnostics(Path::new(filelist), &info);
+        hdl_info.merge_info(&info);
+    }
+    if let Some(input) = args.input.map(PathBuf::from) {
+        if input.is_dir() {
+            let mut entries = fs::read_dir(input)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.retain(|p| p.is_file());
+            entries.sort();
+            files.append(&mut entries);
+        } else {
+            files.push(input);
+        }
+    }
+
+    for file in &files {
+        if args.mfcu {
+            let info = unit.parse_file(file, &params)?;
+            report_diagnostics(file, &info);
+            hdl_info.merge_unit_info(&info);
+        } else {
+            let info = unit.clone().parse_file(file, &params)?;
+            report_diagnostics(file, &info);
             hdl_info.merge_info(&info);
         }
-    } else {
-        let info = sv_parse::parse_file(&input).unwrap();
-        hdl_info.merge_info(&info);
+    }
+
+    if let Some(script) = &args.transform {
+        Transform::from_file(Path::new(script))?.apply(&mut hdl_info)?;
+    }
+
+    let unresolved = hdl_info.get_modules().iter()
+        .flat_map(|m| m.get_ports())
+        .filter(|p| p.get_unresolved().is_some())
+        .count();
+    if args.strict && unresolved > 0 {
+        return Err(format!("{} port width(s) unresolved", unresolved).into());
     }
 
     // println!("parse result: {:#?}", hdl_info);
@@ -45,15 +183,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let json_file = File::create(output.join("hdl_info.json"))?;
     serde_json::to_writer_pretty(json_file, &hdl_info)?;
 
-    let chisel_path = output.join("chisel");
-    let spinal_path = output.join("spinal");
+    if let Some(top) = &args.top {
+        let Some(hierarchy) = hdl_info.hierarchy(top) else {
+            return Err(format!("Top module {} not found, candidates: {}", top, hdl_info.top_modules().join(", ")).into());
+        };
+        print!("{}", hierarchy);
+        let hierarchy_file = File::create(output.join("hierarchy.json"))?;
+        serde_json::to_writer_pretty(hierarchy_file, &hierarchy)?;
 
-    let _chisel_str = ChiselConverter::builder().emit_chisel_string(&hdl_info);
-    ChiselConverter::builder().emit_chisel(&chisel_path, &hdl_info);
-    ChiselConverter::builder().split_bundle().emit_chisel(&chisel_path.join("split_bundle"), &hdl_info);
+        let mut diagram = DiagramEmitter::builder();
+        if let Some(depth) = args.diagram_depth {
+            diagram = diagram.max_depth(depth);
+        }
+        if args.collapse_leaves {
+            diagram = diagram.collapse_leaves();
+        }
+        diagram.emit(&output, &hierarchy);
+    }
 
-    ChiselConverter::builder().emit_spinal(&spinal_path, &hdl_info);
-    ChiselConverter::builder().split_bundle().emit_spinal(&spinal_path.join("split_bundle"), &hdl_info);
+    let mut warnings: Vec<String> = Vec::new();
+    for backend in &backends {
+        backend.warnings(&hdl_info).into_iter().for_each(|w| if !warnings.contains(&w) { warnings.push(w) });
+    }
+    warnings.iter().for_each(|w| eprintln!("Warning: {}", w));
+
+    for backend in backends {
+        backend.emit(&output, &hdl_info)?;
+    }
 
     Ok(())
 }
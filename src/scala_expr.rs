@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::const_eval::{TERNARY_PRECEDENCE, binary_precedence, parse_literal};
+
+/// Scala `Int` expression for the width of a packed range that could not be
+/// evaluated. Names that are neither known values nor package members become
+/// constructor `parameters` of the generated class. Macros are expanded by
+/// the parser before, so the range holds no `` `NAME ``.
+#[derive(Debug, PartialEq)]
+pub struct ScalaWidth {
+    pub expression: String,
+    pub parameters: Vec<String>,
+}
+
+/// SV expression parsed from a width expression.
+enum Expr {
+    Number(String),
+    Name(String),
+    Unary(String, Box<Expr>),
+    Binary(String, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Log2(Box<Expr>),
+}
+
+/// Translate the range `[msb:lsb]` into the Scala expression of its width.
+/// `values` holds the evaluated parameters of the module, substituted for
+/// their names, and `log2` the name of the `$clog2` equivalent of the target.
+pub fn range_width(range: &str, values: &HashMap<String, String>, log2: &str) -> Option<ScalaWidth> {
+    let range = range.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let tokens = tokenize(range)?;
+    let mut pos = 0;
+    let msb = parse_expr(&tokens, &mut pos, 0)?;
+    if tokens.get(pos).map(String::as_str) != Some(":") {
+        return None;
+    }
+    pos += 1;
+    let lsb = parse_expr(&tokens, &mut pos, 0)?;
+    if pos != tokens.len() {
+        return None;
+    }
+
+    let mut emitter = Emitter { values, log2, parameters: Vec::new() };
+    let expression = match (&msb, &lsb) {
+        // [N-1:0] is N bits wide
+        (Expr::Binary(op, n, one), Expr::Number(zero)) if op == "-" && is_number(one, "1") && zero == "0" => {
+            emitter.int(n, 0)
+        }
+        (_, Expr::Number(zero)) if zero == "0" => format!("{} + 1", emitter.int(&msb, SCALA_ADDITIVE)),
+        (Expr::Number(zero), _) if zero == "0" => format!("{} + 1", emitter.int(&lsb, SCALA_ADDITIVE)),
+        _ => format!("math.abs({} - {}) + 1", emitter.int(&msb, SCALA_ADDITIVE), emitter.int(&lsb, SCALA_ADDITIVE + 1)),
+    };
+    Some(ScalaWidth { expression, parameters: emitter.parameters })
+}
+
+fn is_number(expr: &Expr, value: &str) -> bool {
+    matches!(expr, Expr::Number(n) if n == value)
+}
+
+fn tokenize(text: &str) -> Option<Vec<String>> {
+    lazy_static! {
+        static ref TOKEN_RE: Regex = Regex::new(concat!(
+            r"^\s*(?:",
+            r"(\d[\d_]*\s*'[sS]?[dDhHbBoO][0-9a-fA-F_]+|\d[\d_]*)",
+            r"|([A-Za-z_][A-Za-z0-9_$]*(?:::[A-Za-z_][A-Za-z0-9_$]*)*|\$[A-Za-z_][A-Za-z0-9_$]*)",
+            r"|(>>>|<<<|\*\*|<<|>>|<=|>=|==|!=|&&|\|\||[-+*/%<>&|^~!?:(),])",
+            r")",
+        )).unwrap();
+    }
+
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while !rest.trim().is_empty() {
+        let caps = TOKEN_RE.captures(rest)?;
+        let token = caps.get(1).or(caps.get(2)).or(caps.get(3))?.as_str();
+        tokens.push(token.to_string());
+        rest = &rest[caps.get(0)?.end()..];
+    }
+    Some(tokens)
+}
+
+/// Precedence climbing with the SV binding powers of `const_eval`.
+fn parse_expr(tokens: &[String], pos: &mut usize, min_prec: u8) -> Option<Expr> {
+    let mut lhs = parse_primary(tokens, pos)?;
+    while let Some(op) = tokens.get(*pos) {
+        if op == "?" {
+            if TERNARY_PRECEDENCE < min_prec {
+                break;
+            }
+            *pos += 1;
+            let a = parse_expr(tokens, pos, 0)?;
+            if tokens.get(*pos).map(String::as_str) != Some(":") {
+                return None;
+            }
+            *pos += 1;
+            let b = parse_expr(tokens, pos, TERNARY_PRECEDENCE)?;
+            lhs = Expr::Ternary(Box::new(lhs), Box::new(a), Box::new(b));
+            continue;
+        }
+        let Some((prec, right_assoc)) = binary_precedence(op) else {
+            break;
+        };
+        if prec < min_prec {
+            break;
+        }
+        *pos += 1;
+        let rhs = parse_expr(tokens, pos, if right_assoc { prec } else { prec + 1 })?;
+        lhs = Expr::Binary(op.clone(), Box::new(lhs), Box::new(rhs));
+    }
+    Some(lhs)
+}
+
+fn parse_primary(tokens: &[String], pos: &mut usize) -> Option<Expr> {
+    let token = tokens.get(*pos)?.clone();
+    *pos += 1;
+    match token.as_str() {
+        "(" => {
+            let expr = parse_expr(tokens, pos, 0)?;
+            (tokens.get(*pos)? == ")").then(|| *pos += 1)?;
+            Some(expr)
+        }
+        "-" | "+" | "!" | "~" => {
+            // Unary operators bind tighter than any binary operator
+            let operand = parse_primary(tokens, pos)?;
+            Some(Expr::Unary(token, Box::new(operand)))
+        }
+        t if t.starts_with('$') => {
+            (tokens.get(*pos)? == "(").then(|| *pos += 1)?;
+            let mut args = vec![parse_expr(tokens, pos, 0)?];
+            while tokens.get(*pos)? == "," {
+                *pos += 1;
+                args.push(parse_expr(tokens, pos, 0)?);
+            }
+            (tokens.get(*pos)? == ")").then(|| *pos += 1)?;
+            // Only `$clog2` has a Scala equivalent, the casts keep the value
+            match (token.as_str(), args.len()) {
+                ("$clog2", 1) => Some(Expr::Log2(Box::new(args.pop()?))),
+                ("$signed" | "$unsigned", 1) => args.pop(),
+                _ => None,
+            }
+        }
+        t if t.starts_with(|c: char| c.is_ascii_digit()) => {
+            let value = parse_literal(t)?.to_bigint()?;
+            Some(Expr::Number(value.to_string()))
+        }
+        t if t.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) => Some(Expr::Name(token)),
+        _ => None,
+    }
+}
+
+/// Scala precedence of the operators emitted, by their first character.
+const SCALA_ADDITIVE: u8 = 8;
+
+fn scala_precedence(op: &str) -> u8 {
+    match op.chars().next() {
+        Some('*' | '/' | '%') => 9,
+        Some('+' | '-') => SCALA_ADDITIVE,
+        Some('<' | '>') => 6,
+        Some('=' | '!') => 5,
+        Some('&') => 4,
+        Some('^') => 3,
+        Some('|') => 2,
+        _ => 0,
+    }
+}
+
+/// Scala operator with the semantics of an SV operator on `Int`.
+fn scala_operator(op: &str) -> Option<&str> {
+    let op = match op {
+        "<<<" => "<<",
+        ">>" => ">>>",
+        ">>>" => ">>",
+        "+" | "-" | "*" | "/" | "%" | "<<" | "<" | "<=" | ">" | ">=" | "==" | "!=" | "&" | "^" | "|" | "&&" | "||" => op,
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn is_boolean(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binary(op, _, _) => matches!(op.as_str(), "<" | "<=" | ">" | ">=" | "==" | "!=" | "&&" | "||"),
+        Expr::Unary(op, _) => op == "!",
+        _ => false,
+    }
+}
+
+struct Emitter<'a> {
+    values: &'a HashMap<String, String>,
+    log2: &'a str,
+    parameters: Vec<String>,
+}
+
+impl Emitter<'_> {
+    /// Emit `expr` as an `Int`, parenthesized when it binds looser than `min_prec`.
+    fn int(&mut self, expr: &Expr, min_prec: u8) -> String {
+        if is_boolean(expr) {
+            return format!("(if ({}) 1 else 0)", self.boolean(expr, 0));
+        }
+        match expr {
+            Expr::Number(n) => n.clone(),
+            Expr::Name(name) => self.name(name),
+            Expr::Unary(op, a) => match op.as_str() {
+                "+" => self.int(a, min_prec),
+                op => format!("{}{}", op, self.int(a, u8::MAX)),
+            },
+            Expr::Binary(op, a, b) if op == "**" => {
+                format!("math.pow({}, {}).toInt", self.int(a, 0), self.int(b, 0))
+            }
+            Expr::Binary(op, a, b) => {
+                let op = scala_operator(op).unwrap_or(op);
+                let prec = scala_precedence(op);
+                let text = format!("{} {} {}", self.int(a, prec), op, self.int(b, prec + 1));
+                if prec < min_prec { format!("({})", text) } else { text }
+            }
+            Expr::Ternary(c, a, b) => {
+                format!("(if ({}) {} else {})", self.boolean(c, 0), self.int(a, 0), self.int(b, 0))
+            }
+            Expr::Log2(arg) => {
+                let arg = self.int(arg, 0);
+                format!("{}({})", self.log2, arg)
+            }
+        }
+    }
+
+    /// Emit `expr` as a `Boolean`, comparing integers against zero.
+    fn boolean(&mut self, expr: &Expr, min_prec: u8) -> String {
+        let text = match expr {
+            Expr::Binary(op, a, b) if matches!(op.as_str(), "&&" | "||") => {
+                let prec = scala_precedence(op);
+                let text = format!("{} {} {}", self.boolean(a, prec), op, self.boolean(b, prec + 1));
+                return if prec < min_prec { format!("({})", text) } else { text };
+            }
+            Expr::Binary(op, a, b) if is_boolean(expr) => {
+                let prec = scala_precedence(op);
+                let text = format!("{} {} {}", self.int(a, prec + 1), op, self.int(b, prec + 1));
+                return if prec < min_prec { format!("({})", text) } else { text };
+            }
+            Expr::Unary(op, a) if op == "!" => return format!("!{}", self.boolean(a, u8::MAX)),
+            _ => format!("{} != 0", self.int(expr, scala_precedence("!=") + 1)),
+        };
+        if scala_precedence("!=") < min_prec { format!("({})", text) } else { text }
+    }
+
+    fn name(&mut self, name: &str) -> String {
+        if name.contains("::") {
+            return name.replace("::", ".");
+        }
+        if let Some(value) = self.values.get(name) {
+            return value.clone();
+        }
+        if !self.parameters.iter().any(|p| p == name) {
+            self.parameters.push(name.to_string());
+        }
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn width(range: &str) -> Option<(String, Vec<String>)> {
+        let values = HashMap::from([("LANES".to_string(), "4".to_string())]);
+        range_width(range, &values, "log2Ceil").map(|w| (w.expression, w.parameters))
+    }
+
+    #[test]
+    fn translate_width_expressions() {
+        // [N-1:0] 直接取 N，已求值的参数替换为数值
+        assert_eq!(width("[BUS_W-1:0]"), Some(("BUS_W".to_string(), vec!["BUS_W".to_string()])));
+        assert_eq!(width("[BUS_W*LANES-1:0]").unwrap().0, "BUS_W * 4");
+        assert_eq!(width("[DEPTH:0]").unwrap().0, "DEPTH + 1");
+        assert_eq!(width("[0:DEPTH-1]").unwrap().0, "DEPTH - 1 + 1");
+        assert_eq!(width("[HI:LO]").unwrap(), ("math.abs(HI - LO) + 1".to_string(), vec!["HI".to_string(), "LO".to_string()]));
+
+        // 系统函数、包成员和运算符按 Scala 语义转换
+        assert_eq!(width("[$clog2(DEPTH)-1:0]").unwrap().0, "log2Ceil(DEPTH)");
+        assert_eq!(width("[$signed(W)-1:0]").unwrap().0, "W");
+        assert_eq!(width("[cfg_pkg::W-1:0]").unwrap(), ("cfg_pkg.W".to_string(), Vec::new()));
+        assert_eq!(width("[2**A-1:0]").unwrap().0, "math.pow(2, A).toInt");
+        assert_eq!(width("[(1<<A)+B-1:0]").unwrap().0, "(1 << A) + B");
+        assert_eq!(width("[A<<B+1:0]").unwrap().0, "(A << B + 1) + 1");
+        assert_eq!(width("[(A>B?A:B)-1:0]").unwrap().0, "(if (A > B) A else B)");
+        assert_eq!(width("[(A?8'd16:4)-1:0]").unwrap().0, "(if (A != 0) 16 else 4)");
+
+        // 无法转换的表达式
+        assert_eq!(width("[{A,B}:0]"), None);
+        assert_eq!(width("[A-1]"), None);
+        assert_eq!(width("[$bits(W)-1:0]"), None);
+        assert_eq!(width("[$clog2(A, B):0]"), None);
+    }
+}
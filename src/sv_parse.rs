@@ -1,72 +1,215 @@
-
 use std::collections::HashMap;
-use std::path::PathBuf;
-use lazy_static::lazy_static;
-use rhai::{Engine, Scope};
-use regex::{Regex, Captures};
-use sv_parser::{Iter, Locate, Node, RefNode, SyntaxTree, parse_sv, unwrap_node};
+use std::path::{Path, PathBuf};
+use num_traits::Signed;
+use sv_parser::{ConstantExpression, Define, DefineText, Defines, Iter, Locate, Node, NodeEvent, RefNode, RefNodes, SyntaxTree, parse_sv, unwrap_node};
+
+use crate::const_eval::{EvalContext, SvValue, TypeMap, apply_binary, eval_str};
+use crate::hdl_info::{Connection, EdgeEvent, HdlInfo, ParameterOverride, Unresolved};
+
+/// Net or variable declared in a module body. Non-ANSI ports take their
+/// data type and packed range from these once the module has been visited.
+struct BodyDeclaration {
+    name: String,
+    port_type: String,
+    width: String,
+    width_expression: Option<String>,
+    unresolved: Option<Unresolved>,
+}
+
+/// Header information of a non-ANSI module, reconciled with its ports in
+/// `finish_module`.
+#[derive(Default)]
+struct NonAnsiHeader {
+    port_order: Vec<String>,
+    declarations: Vec<BodyDeclaration>,
+}
+
+/// Direction, data type and packed width of the previous ANSI port, inherited
+/// by following ports of the same module header that omit them.
+struct AnsiPortHeader<'a> {
+    direction: &'a str,
+    port_type: &'a str,
+    width: String,
+    width_expression: Option<String>,
+    unresolved: Option<Unresolved>,
+}
+
+/// Hierarchical name prefix and genvar values of one elaboration of a
+/// generate scope.
+#[derive(Clone, Default)]
+struct GenerateBinding {
+    prefix: String,
+    genvars: Vec<(String, i64)>,
+}
+
+/// Generate scope entered while walking a module. Conditional constructs hold
+/// the bindings that select each of their blocks.
+enum GenerateFrame<'a> {
+    Scope(Vec<GenerateBinding>),
+    Branches(Vec<(&'a sv_parser::GenerateBlock, Vec<GenerateBinding>)>),
+}
 
-use crate::hdl_info::HdlInfo;
+/// Upper bound on the iterations of a single generate loop.
+const MAX_GENERATE_ITERATIONS: usize = 4096;
 
-pub fn parse_module(syntax_tree: &SyntaxTree) -> Result<HdlInfo, std::io::Error> {
+/// Preprocessor state of a compilation unit. As with `-mfcu`, every file parsed
+/// through the same unit sees the defines and package parameters of the files
+/// parsed before it.
+#[derive(Clone, Default)]
+pub struct CompilationUnit {
+    include_dirs: Vec<PathBuf>,
+    pre_defines: Defines,
+    defines: Vec<(String, Option<String>, String)>,
+    packages: HashMap<String, SvValue>,
+}
+
+impl CompilationUnit {
+    pub fn builder() -> Self {
+        Self::default()
+    }
+
+    pub fn include_dir(mut self, dir: PathBuf) -> Self {
+        self.include_dirs.push(dir);
+        self
+    }
+
+    /// Predefine `name` as with `+define+NAME=VALUE`.
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        let text = (!value.is_empty()).then(|| DefineText::new(value.to_string(), None));
+        self.pre_defines.insert(name.to_string(), Some(Define::new(name.to_string(), Vec::new(), text)));
+        self.defines.retain(|(n, _, _)| n != name);
+        self.defines.push((name.to_string(), None, value.to_string()));
+        self
+    }
+
+    /// Parse `path` with the defines of the unit, then add the defines in
+    /// effect at the end of `path` to the unit.
+    pub fn parse_file(&mut self, path: &PathBuf, parameters: &HashMap<String, String>) -> Result<HdlInfo, std::io::Error> {
+        let (syntax_tree, defines) = parse_sv(path, &self.pre_defines, &self.include_dirs, false, false)
+            .map_err(|e| std::io::Error::other(format!("parse_file failed [{}]: {}", path.display(), e)))?;
+        let hdl_info = parse_module(&syntax_tree, parameters, self)?;
+
+        self.pre_defines = defines;
+        self.defines = hdl_info.get_defines().iter().map(|d| (d.get_name(), d.get_arguments(), d.get_value())).collect();
+        Ok(hdl_info)
+    }
+
+    fn is_object_like(&self, name: &str) -> bool {
+        matches!(self.pre_defines.get(name), Some(Some(define)) if define.arguments.is_empty())
+    }
+}
+
+pub fn parse_module(syntax_tree: &SyntaxTree, parameters: &HashMap<String, String>, unit: &mut CompilationUnit) -> Result<HdlInfo, std::io::Error> {
     let mut hdl_info: HdlInfo = HdlInfo::new();
 
-    let mut ansi_port_last_dir = "";
-    let mut define_map: HashMap<String, String> = HashMap::new();
+    let mut ansi_port_last: Option<AnsiPortHeader> = None;
+    let mut context = EvalContext::new(syntax_tree, parse_typedefs(syntax_tree));
+    for (name, arguments, value) in &unit.defines {
+        hdl_info.add_define(name, arguments.as_deref(), value);
+        if unit.is_object_like(name) {
+            context.add_define(name, value);
+        }
+    }
+    context.add_package_values(&unit.packages);
+    // Parameters of the current module
+    let mut module_scope: HashMap<String, SvValue> = HashMap::new();
+    let mut non_ansi_header = NonAnsiHeader::default();
 
     for node in syntax_tree {
         match node {
             RefNode::TextMacroDefinition(x) => {
-                if let Some(_) = unwrap_node!(x, TextMacroDefinition) {
-                    let name = if let Some(name) = unwrap_node!(x, TextMacroName) {
-                        let name = get_identifier(name).unwrap();
-                        syntax_tree.get_str(&name).unwrap()
-                    } else {
-                        "unknown"
-                    };
+                let name = if let Some(name) = unwrap_node!(x, TextMacroName) {
+                    let name = get_identifier(name).unwrap();
+                    syntax_tree.get_str(&name).unwrap()
+                } else {
+                    "unknown"
+                };
 
-                    let value = if let Some(RefNode::MacroText(x)) = unwrap_node!(x, MacroText) {
-                        let replacement = x.nodes.0;
-                        syntax_tree.get_str(&replacement).unwrap()
-                    } else {
-                        "unknown"
-                    };
+                let value = if let Some(RefNode::MacroText(x)) = unwrap_node!(x, MacroText) {
+                    let replacement = x.nodes.0;
+                    syntax_tree.get_str(&replacement).unwrap()
+                } else {
+                    // Flag macro without a body
+                    ""
+                };
 
-                    hdl_info.add_define(name, value);
-                    define_map.insert(name.to_string(), value.to_string());
+                let locate = x.nodes.0.nodes.0;
+                if let Some(previous) = hdl_info.get_define(name) {
+                    let previous = previous.get_value();
+                    if previous.split_whitespace().ne(value.split_whitespace()) {
+                        let (source, line) = context.location(&locate);
+                        hdl_info.add_diagnostic(source, line, &format!("`{} redefined from '{}' to '{}'", name, previous.trim(), value.trim()));
+                    }
+                }
+
+                let arguments = x.nodes.2.nodes.1.as_ref().and_then(|x| syntax_tree.get_str(&x.nodes.1));
+                hdl_info.add_define(name, arguments, value);
+                // Macros with arguments are only expanded where they are used
+                match x.nodes.2.nodes.1 {
+                    None => context.add_define(name, value),
+                    Some(_) => context.remove_define(name),
                 }
             }
+            RefNode::UndefineCompilerDirective(x) => {
+                let id = get_identifier(unwrap_node!(x, TextMacroIdentifier).unwrap()).unwrap();
+                let name = syntax_tree.get_str(&id).unwrap();
+                if hdl_info.get_define(name).is_none() {
+                    let (source, line) = context.location(&id);
+                    hdl_info.add_diagnostic(source, line, &format!("`undef of undefined macro `{}", name));
+                }
+
+                hdl_info.remove_define(name);
+                context.remove_define(name);
+            }
+            RefNode::UndefineallCompilerDirective(_) => {
+                for define in hdl_info.get_defines().clone() {
+                    hdl_info.remove_define(&define.get_name());
+                    context.remove_define(&define.get_name());
+                }
+            }
+            RefNode::PackageDeclaration(x) => {
+                let id = get_identifier(RefNode::PackageIdentifier(&x.nodes.3)).unwrap();
+                let package = syntax_tree.get_str(&id).unwrap();
+
+                hdl_info.add_package(package);
+                let mut names = Vec::new();
+                let items = nodes_outside(x.into(), is_nested_scope);
+                let scope = parse_parameters(syntax_tree, &items, &context, &HashMap::new(), |n, v, e, l| {
+                    names.push(n.to_string());
+                    hdl_info.add_package_parameter(n, v, e, l);
+                });
+                let values = names.iter()
+                    .filter_map(|n| Some((format!("{}::{}", package, n), scope.get(n)?.clone())))
+                    .collect();
+                context.add_package_values(&values);
+            }
             RefNode::ModuleDeclaration(x) => {
+                finish_module(&mut hdl_info, &mut non_ansi_header);
+
                 let id = unwrap_node!(x, ModuleIdentifier).unwrap();
                 let id = get_identifier(id).unwrap();
                 let name = syntax_tree.get_str(&id).unwrap();
 
                 hdl_info.add_module(name);
+                if let Some(path) = source_of(syntax_tree, &id) {
+                    hdl_info.set_module_source(&path);
+                }
+                ansi_port_last = None;
+                let items = nodes_outside(x.into(), is_nested_scope);
+                module_scope = parse_parameters(syntax_tree, &items, &context, parameters, |n, v, e, l| hdl_info.add_parameter(n, v, e, l));
+                let skipped_blocks = parse_generate_instances(syntax_tree, x, &module_scope, &context, &mut hdl_info);
+                non_ansi_header = parse_non_ansi_header(syntax_tree, x, &skipped_blocks, &module_scope, &context);
+            }
+            RefNode::AlwaysConstruct(x) => {
+                hdl_info.add_edge_events(parse_edge_events(syntax_tree, x));
             }
             RefNode::PortDeclaration(x) => {
                 if let Some(id) = unwrap_node!(x, InputDeclaration, OutputDeclaration, InoutDeclaration) {
                     let id = get_identifier(id).unwrap();
                     let direction = syntax_tree.get_str(&id).unwrap();
 
-                    let port_type = match unwrap_node!(x, DataType, ImplicitDataType) {
-                        Some(RefNode::DataType(x)) => {
-                            let id = unwrap_node!(x, Keyword);
-                            if id != None {
-                                syntax_tree.get_str(&get_identifier(id.unwrap()).unwrap()).unwrap()
-                            } else {
-                                "unknown"
-                            }
-                        },
-                        Some(RefNode::ImplicitDataType(_)) => "wire",
-                        _ => "unknown"
-                    };
-
-                    let (width, width_expression) = match unwrap_node!(x, PackedDimensionRange) {
-                        Some(RefNode::PackedDimensionRange(x)) => {
-                            parse_packed_dimension_range(syntax_tree, &x.clone(), &define_map)
-                        }
-                        _ => ("1".to_string(), None)
-                    };
+                    let port_type = parse_data_type(syntax_tree, x);
+                    let (width, width_expression, unresolved) = parse_packed_width(syntax_tree, x, &module_scope, &context);
 
                     if let Some(RefNode::ListOfPortIdentifiers(x)) = unwrap_node!(x, ListOfPortIdentifiers) {
                         for node in x {
@@ -75,7 +218,7 @@ pub fn parse_module(syntax_tree: &SyntaxTree) -> Result<HdlInfo, std::io::Error>
                                 let id = get_identifier(id).unwrap();
                                 let name = syntax_tree.get_str(&id).unwrap();
 
-                                hdl_info.add_ports(name, direction, port_type, width.as_str(), &width_expression);
+                                hdl_info.add_ports(name, direction, port_type, width.as_str(), &width_expression, &unresolved);
                             }
                         }
                     }
@@ -86,48 +229,610 @@ pub fn parse_module(syntax_tree: &SyntaxTree) -> Result<HdlInfo, std::io::Error>
                     let name_locate = get_identifier(id).unwrap();
                     let name = syntax_tree.get_str(&name_locate).unwrap();
 
-                    let id = unwrap_node!(x, PortDirection);
-                    if id != None {
-                        let id = id.unwrap();
-                        let dir_locate = get_identifier(id).unwrap();
-                        ansi_port_last_dir = syntax_tree.get_str(&dir_locate).unwrap();
+                    let has_header = match x {
+                        sv_parser::AnsiPortDeclaration::Net(x) => x.nodes.0.is_some(),
+                        sv_parser::AnsiPortDeclaration::Variable(x) => x.nodes.0.is_some(),
+                        sv_parser::AnsiPortDeclaration::Paren(x) => x.nodes.0.is_some(),
                     };
 
-                    let port_type = if unwrap_node!(x, AnsiPortDeclarationVariable) != None {
-                        "wire"
-                    } else {
-                        match unwrap_node!(x, DataType, ImplicitDataType) {
-                            Some(RefNode::DataType(x)) => {
-                                let id = unwrap_node!(x, Keyword);
-                                if id != None {
-                                    syntax_tree.get_str(&get_identifier(id.unwrap()).unwrap()).unwrap()
-                                } else {
-                                    "unknown"
-                                }
-                            },
-                            Some(RefNode::ImplicitDataType(_)) => "wire",
-                            _ => "unknown"
-                        }
-                    };
+                    // A port without direction, kind or data type inherits all
+                    // of them from the previous port of the same header.
+                    let header = match (has_header, ansi_port_last.take()) {
+                        (false, Some(last)) => last,
+                        (_, last) => {
+                            let direction = match unwrap_node!(x, PortDirection) {
+                                Some(id) => syntax_tree.get_str(&get_identifier(id).unwrap()).unwrap(),
+                                None => last.map_or("inout", |l| l.direction),
+                            };
 
-                    let (width, width_expression) = match unwrap_node!(x, PackedDimensionRange) {
-                        Some(RefNode::PackedDimensionRange(x)) => {
-                            parse_packed_dimension_range(syntax_tree, &x.clone(), &define_map)
+                            let port_type = match parse_data_type(syntax_tree, x) {
+                                "wire" if unwrap_node!(x, VarDataType).is_some() => "logic",
+                                port_type => port_type,
+                            };
+
+                            let (width, width_expression, unresolved) = parse_packed_width(syntax_tree, x, &module_scope, &context);
+
+                            AnsiPortHeader { direction, port_type, width, width_expression, unresolved }
                         }
-                        _ => ("1".to_string(), None)
                     };
 
-                    hdl_info.add_ports(name, ansi_port_last_dir, port_type, width.as_str(), &width_expression);
+                    hdl_info.add_ports(name, header.direction, header.port_type, header.width.as_str(), &header.width_expression, &header.unresolved);
+                    ansi_port_last = Some(header);
                 }
             }
-            // Can add process of comment, parameter,instantiation and keyword
+            // Can add process of comment, parameter and keyword
             _ =>  ()
         }
     }
+    finish_module(&mut hdl_info, &mut non_ansi_header);
+    unit.packages = context.package_values().clone();
+
+    // Defines are evaluated with the definitions in effect at the end of the
+    // file. String and flag macros have no constant value.
+    for define in hdl_info.get_defines().clone() {
+        let body = define.get_value();
+        if body.trim().is_empty() || body.contains('"') {
+            continue;
+        }
+        if let Some(value) = context.define_value(&define.get_name()) {
+            hdl_info.set_define_value(&define.get_name(), &value.to_string());
+        }
+    }
 
     Ok(hdl_info)
 }
 
+/// Evaluate the parameters among the module or package `items` in source
+/// order and pass them to `record` as (name, value, expression, local).
+/// Returns the parameter values, with `overrides` replacing the defaults of
+/// non-local parameters, together with the package parameters it imports.
+/// Values are converted to the declared type of their parameter.
+fn parse_parameters(
+    syntax_tree: &SyntaxTree,
+    items: &[RefNode],
+    context: &EvalContext,
+    overrides: &HashMap<String, String>,
+    mut record: impl FnMut(&str, &str, &str, bool),
+) -> HashMap<String, SvValue> {
+    let mut local_names = Vec::new();
+    let mut scope = HashMap::new();
+    for node in items.iter().cloned() {
+        match node {
+            RefNode::LocalParameterDeclaration(x) => {
+                for node in x {
+                    if let RefNode::ParamAssignment(x) = node {
+                        let id = get_identifier(RefNode::ParameterIdentifier(&x.nodes.0)).unwrap();
+                        local_names.push(syntax_tree.get_str(&id).unwrap());
+                    }
+                }
+            }
+            RefNode::PackageImportItem(x) => {
+                let (package, item) = match x {
+                    sv_parser::PackageImportItem::Identifier(x) => {
+                        let item = get_identifier(RefNode::Identifier(&x.nodes.2)).unwrap();
+                        (&x.nodes.0, syntax_tree.get_str(&item))
+                    }
+                    sv_parser::PackageImportItem::Asterisk(x) => (&x.nodes.0, None),
+                };
+                let package = get_identifier(RefNode::PackageIdentifier(package)).unwrap();
+                scope.extend(context.imported(syntax_tree.get_str(&package).unwrap(), item));
+            }
+            _ => (),
+        }
+    }
+
+    // Data type of the declaration the following assignments belong to
+    let mut data_type = None;
+    for node in items.iter().cloned() {
+        match node {
+            RefNode::ParameterDeclarationParam(x) => data_type = Some(RefNode::DataTypeOrImplicit(&x.nodes.1)),
+            RefNode::LocalParameterDeclarationParam(x) => data_type = Some(RefNode::DataTypeOrImplicit(&x.nodes.1)),
+            RefNode::ParameterPortDeclarationParamList(x) => data_type = Some(RefNode::DataType(&x.nodes.0)),
+            _ => (),
+        }
+        if let RefNode::ParamAssignment(x) = node {
+            let id = get_identifier(RefNode::ParameterIdentifier(&x.nodes.0)).unwrap();
+            let name = syntax_tree.get_str(&id).unwrap();
+            let Some((_, default)) = &x.nodes.2 else {
+                continue;
+            };
+
+            let local = local_names.contains(&name);
+            let (expression, value) = match overrides.get(name) {
+                Some(value) if !local => (value.as_str(), eval_str(value, &scope)),
+                _ => {
+                    let value = match default {
+                        sv_parser::ConstantParamExpression::ConstantMintypmaxExpression(x) => match x.as_ref() {
+                            sv_parser::ConstantMintypmaxExpression::Unary(x) => context.evaluator(&scope).eval(x),
+                            sv_parser::ConstantMintypmaxExpression::Ternary(x) => context.evaluator(&scope).eval(&x.nodes.2),
+                        },
+                        _ => None,
+                    };
+                    (syntax_tree.get_str_trim(default).unwrap(), value)
+                }
+            };
+            let value = match (value, data_type.clone()) {
+                (Some(value), Some(data_type)) => context.evaluator(&scope).convert(data_type, value),
+                (value, _) => value,
+            };
+
+            record(name, &value.as_ref().map_or(expression.to_string(), |v| v.to_string()), expression, local);
+            if let Some(value) = value {
+                scope.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    scope
+}
+
+/// Walk a module and record its instances, elaborating generate constructs
+/// against the module scope. Instances in loops are recorded once per
+/// iteration as `label[i].u_x`; when a condition or loop bound cannot be
+/// evaluated every branch is kept. Returns the generate blocks that are not
+/// elaborated.
+fn parse_generate_instances<'a>(
+    syntax_tree: &SyntaxTree,
+    module: &'a sv_parser::ModuleDeclaration,
+    scope: &HashMap<String, SvValue>,
+    context: &EvalContext,
+    hdl_info: &mut HdlInfo,
+) -> Vec<&'a sv_parser::GenerateBlock> {
+    let mut frames = vec![GenerateFrame::Scope(vec![GenerateBinding::default()])];
+    let mut skipped_blocks = Vec::new();
+
+    for event in Iter::new(module.into()).event() {
+        match event {
+            NodeEvent::Enter(RefNode::LoopGenerateConstruct(x)) => {
+                let bindings = active_bindings(&frames).iter()
+                    .flat_map(|b| loop_generate_bindings(syntax_tree, x, b, scope, context, hdl_info))
+                    .collect();
+                frames.push(GenerateFrame::Scope(bindings));
+            }
+            NodeEvent::Enter(RefNode::IfGenerateConstruct(x)) => {
+                let (mut then_bindings, mut else_bindings) = (Vec::new(), Vec::new());
+                for b in active_bindings(&frames) {
+                    match eval_generate_expr(&x.nodes.1.nodes.1, b, scope, context) {
+                        Some(0) => else_bindings.push(b.clone()),
+                        Some(_) => then_bindings.push(b.clone()),
+                        None => {
+                            then_bindings.push(b.clone());
+                            else_bindings.push(b.clone());
+                        }
+                    }
+                }
+
+                let mut branches = vec![(&x.nodes.2, then_bindings)];
+                if let Some((_, block)) = &x.nodes.3 {
+                    branches.push((block, else_bindings));
+                }
+                frames.push(GenerateFrame::Branches(branches));
+            }
+            NodeEvent::Enter(RefNode::CaseGenerateConstruct(x)) => {
+                let mut branches: Vec<(&sv_parser::GenerateBlock, Vec<GenerateBinding>)> = Vec::new();
+                for b in active_bindings(&frames) {
+                    let selector = eval_generate_expr(&x.nodes.1.nodes.1, b, scope, context);
+                    let mut default = None;
+                    let mut selected = Vec::new();
+                    for item in &x.nodes.2 {
+                        match item {
+                            sv_parser::CaseGenerateItem::Nondefault(item) => {
+                                let matches = item.nodes.0.contents().iter().any(|e| {
+                                    let value = eval_generate_expr(e, b, scope, context);
+                                    selector.is_none() || value.is_none() || value == selector
+                                });
+                                if matches && (selector.is_none() || selected.is_empty()) {
+                                    selected.push(&item.nodes.2);
+                                }
+                            }
+                            sv_parser::CaseGenerateItem::Default(item) => default = Some(&item.nodes.2),
+                        }
+                    }
+                    if selected.is_empty() || selector.is_none() {
+                        selected.extend(default);
+                    }
+
+                    for block in selected {
+                        match branches.iter_mut().find(|(x, _)| std::ptr::eq(*x, block)) {
+                            Some((_, bindings)) => bindings.push(b.clone()),
+                            None => branches.push((block, vec![b.clone()])),
+                        }
+                    }
+                }
+                frames.push(GenerateFrame::Branches(branches));
+            }
+            NodeEvent::Enter(RefNode::GenerateBlock(x)) => {
+                let bindings = match frames.last() {
+                    Some(GenerateFrame::Branches(branches)) => {
+                        let label = generate_block_label(syntax_tree, x);
+                        branches.iter()
+                            .filter(|(block, _)| std::ptr::eq(*block, x))
+                            .flat_map(|(_, bindings)| bindings.iter().map(|b| GenerateBinding {
+                                prefix: match label {
+                                    Some(label) => format!("{}{}.", b.prefix, label),
+                                    None => b.prefix.clone(),
+                                },
+                                genvars: b.genvars.clone(),
+                            }))
+                            .collect()
+                    }
+                    _ => active_bindings(&frames).to_vec(),
+                };
+                if bindings.is_empty() {
+                    skipped_blocks.push(x);
+                }
+                frames.push(GenerateFrame::Scope(bindings));
+            }
+            NodeEvent::Leave(RefNode::LoopGenerateConstruct(_))
+            | NodeEvent::Leave(RefNode::IfGenerateConstruct(_))
+            | NodeEvent::Leave(RefNode::CaseGenerateConstruct(_))
+            | NodeEvent::Leave(RefNode::GenerateBlock(_)) => {
+                frames.pop();
+            }
+            NodeEvent::Enter(RefNode::ModuleInstantiation(x)) => {
+                for b in active_bindings(&frames) {
+                    parse_module_instantiation(syntax_tree, x, &b.prefix, hdl_info);
+                }
+            }
+            _ => ()
+        }
+    }
+
+    skipped_blocks
+}
+
+/// Bindings of the innermost generate scope.
+fn active_bindings<'a>(frames: &'a [GenerateFrame]) -> &'a [GenerateBinding] {
+    frames.iter().rev()
+        .find_map(|f| match f {
+            GenerateFrame::Scope(bindings) => Some(bindings.as_slice()),
+            GenerateFrame::Branches(_) => None,
+        })
+        .unwrap_or_default()
+}
+
+fn generate_block_label<'a>(syntax_tree: &'a SyntaxTree, x: &sv_parser::GenerateBlock) -> Option<&'a str> {
+    match x {
+        sv_parser::GenerateBlock::Multiple(x) => {
+            let id = x.nodes.0.as_ref().map(|(id, _)| id).or(x.nodes.2.as_ref().map(|(_, id)| id))?;
+            syntax_tree.get_str(&get_identifier(RefNode::GenerateBlockIdentifier(id)).unwrap())
+        }
+        sv_parser::GenerateBlock::GenerateItem(_) => None,
+    }
+}
+
+/// One binding per iteration of `for (genvar i = ...; cond; step)`. A loop
+/// whose bounds cannot be evaluated, or that runs for more than
+/// `MAX_GENERATE_ITERATIONS` iterations, yields a single symbolic `label[i]`
+/// scope; the latter is reported as a diagnostic.
+fn loop_generate_bindings(
+    syntax_tree: &SyntaxTree,
+    x: &sv_parser::LoopGenerateConstruct,
+    binding: &GenerateBinding,
+    scope: &HashMap<String, SvValue>,
+    context: &EvalContext,
+    hdl_info: &mut HdlInfo,
+) -> Vec<GenerateBinding> {
+    let (init, _, condition, _, step) = &x.nodes.1.nodes.1;
+    let id = get_identifier(RefNode::GenvarIdentifier(&init.nodes.1)).unwrap();
+    let genvar = syntax_tree.get_str(&id).unwrap();
+    let label = generate_block_label(syntax_tree, &x.nodes.2).unwrap_or("genblk");
+
+    let with_genvar = |value: i64| {
+        let mut genvars = binding.genvars.clone();
+        genvars.push((genvar.to_string(), value));
+        GenerateBinding { prefix: format!("{}{}[{}].", binding.prefix, label, value), genvars }
+    };
+
+    let mut bindings = Vec::new();
+    let mut value = eval_generate_expr(&init.nodes.3, binding, scope, context);
+    while let Some(v) = value {
+        let b = with_genvar(v);
+        match eval_generate_expr(&condition.nodes.0, &b, scope, context) {
+            Some(0) => break,
+            Some(_) => (),
+            None => {
+                value = None;
+                break;
+            }
+        }
+        value = match step {
+            sv_parser::GenvarIteration::Assignment(x) => {
+                let operator = syntax_tree.get_str_trim(&x.nodes.1).unwrap();
+                let expression = eval_generate_expr(&x.nodes.2.nodes.0, &b, scope, context);
+                match operator.strip_suffix('=') {
+                    Some(op) if !op.is_empty() => {
+                        apply_binary(op, Some(SvValue::from_i64(v)), expression.map(SvValue::from_i64)).and_then(|v| v.to_i64())
+                    }
+                    _ => expression,
+                }
+            }
+            sv_parser::GenvarIteration::Prefix(_) | sv_parser::GenvarIteration::Suffix(_) => {
+                match syntax_tree.get_str_trim(step).unwrap().contains("++") {
+                    true => Some(v + 1),
+                    false => Some(v - 1),
+                }
+            }
+        };
+        bindings.push(b);
+        if bindings.len() > MAX_GENERATE_ITERATIONS {
+            let message = format!("generate loop {}{} exceeds {} iterations and is kept as {}{}[{}]",
+                binding.prefix, label, MAX_GENERATE_ITERATIONS, binding.prefix, label, genvar);
+            let (source, line) = context.location(&x.nodes.0.nodes.0);
+            hdl_info.add_diagnostic(source, line, &message);
+            bindings.clear();
+            value = None;
+            break;
+        }
+    }
+
+    if value.is_none() && bindings.is_empty() {
+        bindings.push(GenerateBinding {
+            prefix: format!("{}{}[{}].", binding.prefix, label, genvar),
+            genvars: binding.genvars.clone(),
+        });
+    }
+    bindings
+}
+
+fn eval_generate_expr(
+    expr: &ConstantExpression,
+    binding: &GenerateBinding,
+    scope: &HashMap<String, SvValue>,
+    context: &EvalContext,
+) -> Option<i64> {
+    let genvars: Vec<(String, SvValue)> = binding.genvars.iter()
+        .map(|(genvar, value)| (genvar.clone(), SvValue::from_i64(*value)))
+        .collect();
+    context.evaluator(scope).locals(&genvars).eval(expr)?.to_i64()
+}
+
+/// Record every instance of `u_a (...), u_b (...);` with its parameter
+/// overrides and port connections on the last module, prefixing instance
+/// names with their generate scope.
+fn parse_module_instantiation(
+    syntax_tree: &SyntaxTree,
+    x: &sv_parser::ModuleInstantiation,
+    prefix: &str,
+    hdl_info: &mut HdlInfo,
+) {
+    let id = get_identifier(RefNode::ModuleIdentifier(&x.nodes.0)).unwrap();
+    let module_type = syntax_tree.get_str(&id).unwrap();
+
+    let mut parameters = Vec::new();
+    if let Some(x) = &x.nodes.1 {
+        for node in x {
+            match node {
+                RefNode::OrderedParameterAssignment(x) => {
+                    let value = syntax_tree.get_str_trim(x).unwrap();
+                    parameters.push(ParameterOverride::new(None, value));
+                }
+                RefNode::NamedParameterAssignment(x) => {
+                    let id = get_identifier(RefNode::ParameterIdentifier(&x.nodes.1)).unwrap();
+                    let name = syntax_tree.get_str(&id).unwrap();
+                    if let Some(value) = &x.nodes.2.nodes.1 {
+                        parameters.push(ParameterOverride::new(Some(name), syntax_tree.get_str_trim(value).unwrap()));
+                    }
+                }
+                _ => ()
+            }
+        }
+    }
+
+    for instance in x.nodes.2.contents() {
+        let id = get_identifier(RefNode::InstanceIdentifier(&instance.nodes.0.nodes.0)).unwrap();
+        let name = syntax_tree.get_str(&id).unwrap();
+
+        let mut connections = Vec::new();
+        for node in instance {
+            match node {
+                RefNode::OrderedPortConnection(x) => {
+                    let expression = x.nodes.1.as_ref().map(|e| syntax_tree.get_str_trim(e).unwrap());
+                    connections.push(Connection::new(None, expression));
+                }
+                RefNode::NamedPortConnectionIdentifier(x) => {
+                    let id = get_identifier(RefNode::PortIdentifier(&x.nodes.2)).unwrap();
+                    let port = syntax_tree.get_str(&id).unwrap();
+                    // `.a` is an implicit connection to the signal of the same name
+                    let expression = match &x.nodes.3 {
+                        Some(paren) => paren.nodes.1.as_ref().map(|e| syntax_tree.get_str_trim(e).unwrap()),
+                        None => Some(port),
+                    };
+                    connections.push(Connection::new(Some(port), expression));
+                }
+                RefNode::NamedPortConnectionAsterisk(_) => {
+                    connections.push(Connection::new(Some("*"), None));
+                }
+                _ => ()
+            }
+        }
+
+        hdl_info.add_instance(&format!("{}{}", prefix, name), module_type, parameters.clone(), connections);
+    }
+}
+
+/// Collect the header port order and body net/variable declarations of a
+/// non-ANSI module (`module m(a, b); input a; output [7:0] b; reg [7:0] b;`).
+fn parse_non_ansi_header(
+    syntax_tree: &SyntaxTree,
+    module: &sv_parser::ModuleDeclaration,
+    skipped_blocks: &[&sv_parser::GenerateBlock],
+    scope: &HashMap<String, SvValue>,
+    context: &EvalContext,
+) -> NonAnsiHeader {
+    let mut header = NonAnsiHeader::default();
+
+    // Declarations of functions and tasks are local to them, those of generate
+    // blocks only count in the elaborated branch
+    let skip = |node: &RefNode| match node {
+        RefNode::FunctionDeclaration(_) | RefNode::TaskDeclaration(_) => true,
+        RefNode::GenerateBlock(x) => skipped_blocks.iter().any(|b| std::ptr::eq(*b, *x)),
+        _ => false,
+    };
+    for node in nodes_outside(module.into(), skip) {
+        match node {
+            RefNode::ListOfPorts(x) => {
+                for node in x {
+                    if let RefNode::PortReference(x) = node {
+                        let id = get_identifier(RefNode::PortIdentifier(&x.nodes.0)).unwrap();
+                        header.port_order.push(syntax_tree.get_str(&id).unwrap().to_string());
+                    }
+                }
+            }
+            RefNode::NetDeclarationNetType(x) => {
+                let id = get_identifier(RefNode::NetType(&x.nodes.0)).unwrap();
+                let port_type = syntax_tree.get_str(&id).unwrap();
+                let (width, width_expression, unresolved) = parse_packed_width(syntax_tree, x, scope, context);
+
+                for node in x {
+                    if let RefNode::NetIdentifier(x) = node {
+                        let id = get_identifier(RefNode::NetIdentifier(x)).unwrap();
+                        header.declarations.push(BodyDeclaration {
+                            name: syntax_tree.get_str(&id).unwrap().to_string(),
+                            port_type: port_type.to_string(),
+                            width: width.clone(),
+                            width_expression: width_expression.clone(),
+                            unresolved: unresolved.clone(),
+                        });
+                    }
+                }
+            }
+            RefNode::DataDeclarationVariable(x) => {
+                let port_type = parse_data_type(syntax_tree, x);
+                let (width, width_expression, unresolved) = parse_packed_width(syntax_tree, x, scope, context);
+
+                for node in x {
+                    if let RefNode::VariableIdentifier(x) = node {
+                        let id = get_identifier(RefNode::VariableIdentifier(x)).unwrap();
+                        header.declarations.push(BodyDeclaration {
+                            name: syntax_tree.get_str(&id).unwrap().to_string(),
+                            port_type: port_type.to_string(),
+                            width: width.clone(),
+                            width_expression: width_expression.clone(),
+                            unresolved: unresolved.clone(),
+                        });
+                    }
+                }
+            }
+            _ => ()
+        }
+    }
+
+    header
+}
+
+/// Nodes of `x` in source order, leaving out the subtrees for which `skip`
+/// holds.
+fn nodes_outside<'a>(x: RefNodes<'a>, skip: impl Fn(&RefNode<'a>) -> bool) -> Vec<RefNode<'a>> {
+    let mut nodes = Vec::new();
+    let mut depth = 0;
+    for event in Iter::new(x).event() {
+        match event {
+            NodeEvent::Enter(node) if depth > 0 || skip(&node) => depth += 1,
+            NodeEvent::Enter(node) => nodes.push(node),
+            NodeEvent::Leave(_) if depth > 0 => depth -= 1,
+            NodeEvent::Leave(_) => (),
+        }
+    }
+    nodes
+}
+
+/// Functions, tasks, classes and generate constructs, whose declarations are
+/// not items of the enclosing module or package.
+fn is_nested_scope(node: &RefNode) -> bool {
+    matches!(node,
+        RefNode::FunctionDeclaration(_) | RefNode::TaskDeclaration(_) | RefNode::ClassDeclaration(_)
+        | RefNode::LoopGenerateConstruct(_) | RefNode::IfGenerateConstruct(_) | RefNode::CaseGenerateConstruct(_))
+}
+
+/// Typedefs of the file by name, members of a package also as `pkg::name`.
+fn parse_typedefs(syntax_tree: &SyntaxTree) -> TypeMap<'_> {
+    let mut types = TypeMap::new();
+    let mut package = None;
+
+    for event in syntax_tree.into_iter().event() {
+        match event {
+            NodeEvent::Enter(RefNode::PackageDeclaration(x)) => {
+                let id = get_identifier(RefNode::PackageIdentifier(&x.nodes.3)).unwrap();
+                package = syntax_tree.get_str(&id);
+            }
+            NodeEvent::Leave(RefNode::PackageDeclaration(_)) => package = None,
+            NodeEvent::Enter(RefNode::TypeDeclarationDataType(x)) => {
+                let id = get_identifier(RefNode::TypeIdentifier(&x.nodes.2)).unwrap();
+                let name = syntax_tree.get_str(&id).unwrap();
+                if let Some(package) = package {
+                    types.insert(format!("{}::{}", package, name), x);
+                }
+                types.insert(name.to_string(), x);
+            }
+            _ => ()
+        }
+    }
+
+    types
+}
+
+/// Merge body declarations into the ports of the last module and restore the
+/// port order given by its header list.
+fn finish_module(hdl_info: &mut HdlInfo, header: &mut NonAnsiHeader) {
+    for d in header.declarations.drain(..) {
+        hdl_info.merge_port_declaration(&d.name, &d.port_type, &d.width, &d.width_expression, &d.unresolved);
+    }
+    if !header.port_order.is_empty() {
+        hdl_info.reorder_ports(&header.port_order);
+    }
+    header.port_order.clear();
+
+    let warnings: Vec<(Unresolved, String)> = hdl_info.get_modules().last().into_iter()
+        .flat_map(|m| m.get_ports())
+        .filter_map(|p| {
+            let unresolved = p.get_unresolved()?;
+            let reason = match (unresolved.get_identifier(), unresolved.get_reason()) {
+                (Some(id), _) => format!(", `{}` not found", id),
+                (None, Some(reason)) => format!(", {}", reason),
+                (None, None) => String::new(),
+            };
+            Some((unresolved, format!("width {} of port {} is unresolved{}", p.get_width(), p.get_name(), reason)))
+        })
+        .collect();
+    for (unresolved, message) in warnings {
+        hdl_info.add_diagnostic(unresolved.get_source(), unresolved.get_line(), &message);
+    }
+}
+
+fn parse_data_type<'a, N>(syntax_tree: &'a SyntaxTree, x: N) -> &'a str
+where
+    N: IntoIterator<Item = RefNode<'a>>,
+{
+    match unwrap_node!(x, DataType, ImplicitDataType) {
+        Some(RefNode::DataType(x)) => {
+            match unwrap_node!(x, Keyword) {
+                Some(id) => syntax_tree.get_str(&get_identifier(id).unwrap()).unwrap(),
+                None => "unknown"
+            }
+        },
+        Some(RefNode::ImplicitDataType(_)) => "wire",
+        _ => "unknown"
+    }
+}
+
+fn parse_packed_width<'a, N>(
+    syntax_tree: &SyntaxTree,
+    x: N,
+    scope: &HashMap<String, SvValue>,
+    context: &EvalContext,
+) -> (String, Option<String>, Option<Unresolved>)
+where
+    N: IntoIterator<Item = RefNode<'a>>,
+{
+    match unwrap_node!(x, PackedDimensionRange) {
+        Some(RefNode::PackedDimensionRange(x)) => {
+            parse_packed_dimension_range(syntax_tree, x, scope, context)
+        }
+        _ => ("1".to_string(), None, None)
+    }
+}
+
 fn parse_expression<'a, N>(syntax_tree: &SyntaxTree, x: &'a N) -> (String, Option<Locate>)
 where
     N: Node<'a>,
@@ -137,13 +842,22 @@ where
 
     for node in Iter::new(x.next()) {
         // println!("parse expression::node {:#?}", node);
-        match unwrap_node!(node, SimpleIdentifier, Symbol, UnsignedNumber, HexNumber, OctalNumber, BinaryNumber) {
+        match unwrap_node!(node, SimpleIdentifier, SystemTfIdentifier, Symbol, UnsignedNumber, HexNumber, OctalNumber, BinaryNumber) {
             Some(RefNode::SimpleIdentifier(x)) => {
                 let locate = x.nodes.0;
                 if locate != last_locate {
                     last_locate = locate;
                     let s = syntax_tree.get_str(&locate).unwrap();
-                    expression = expression + s;
+                    expression += s;
+                    // println!("parse expression {}", s);
+                }
+            }
+            Some(RefNode::SystemTfIdentifier(x)) => {
+                let locate = x.nodes.0;
+                if locate != last_locate {
+                    last_locate = locate;
+                    let s = syntax_tree.get_str(&locate).unwrap();
+                    expression += s;
                     // println!("parse expression {}", s);
                 }
             }
@@ -152,7 +866,7 @@ where
                 if locate != last_locate {
                     last_locate = locate;
                     let s = syntax_tree.get_str(&x.nodes.0).unwrap();
-                    expression = expression + s;
+                    expression += s;
                     // println!("parse expression {}", s);
                 }
             }
@@ -161,7 +875,7 @@ where
                 if locate != last_locate {
                     last_locate = locate;
                     let s = syntax_tree.get_str(&x.nodes.0).unwrap();
-                    expression = expression + s;
+                    expression += s;
                     // println!("parse expression {}", s);
                 }
             }
@@ -169,7 +883,7 @@ where
                 let locate = x.nodes.1.nodes.0;
                 if locate != last_locate {
                     last_locate = locate;
-                    let size = if x.nodes.0 != None { syntax_tree.get_str(&x.nodes.0).unwrap() } else { "" };
+                    let size = if x.nodes.0.is_some() { syntax_tree.get_str(&x.nodes.0).unwrap() } else { "" };
                     let base = syntax_tree.get_str(&x.nodes.1.nodes.0).unwrap();
                     let number = syntax_tree.get_str(&x.nodes.2.nodes.0).unwrap();
                     expression = expression + size + base + number;
@@ -180,7 +894,7 @@ where
                 let locate = x.nodes.1.nodes.0;
                 if locate != last_locate {
                     last_locate = locate;
-                    let size = if x.nodes.0 != None { syntax_tree.get_str(&x.nodes.0).unwrap() } else { "" };
+                    let size = if x.nodes.0.is_some() { syntax_tree.get_str(&x.nodes.0).unwrap() } else { "" };
                     let base = syntax_tree.get_str(&x.nodes.1.nodes.0).unwrap();
                     let number = syntax_tree.get_str(&x.nodes.2.nodes.0).unwrap();
                     expression = expression + size + base + number;
@@ -191,7 +905,7 @@ where
                 let locate = x.nodes.1.nodes.0;
                 if locate != last_locate {
                     last_locate = locate;
-                    let size = if x.nodes.0 != None { syntax_tree.get_str(&x.nodes.0).unwrap() } else { "" };
+                    let size = if x.nodes.0.is_some() { syntax_tree.get_str(&x.nodes.0).unwrap() } else { "" };
                     let base = syntax_tree.get_str(&x.nodes.1.nodes.0).unwrap();
                     let number = syntax_tree.get_str(&x.nodes.2.nodes.0).unwrap();
                     expression = expression + size + base + number;
@@ -201,7 +915,7 @@ where
             _ => ()
         }
     }
-    if expression == "" {
+    if expression.is_empty() {
         ("unknown".to_string(), None)
     } else {
         // println!("parse function lastlocate {:?}", last_locate);
@@ -212,137 +926,118 @@ where
 fn parse_packed_dimension_range(
     syntax_tree: &SyntaxTree,
     x: &sv_parser::PackedDimensionRange,
-    defines: &HashMap<String, String>,
-) -> (String, Option<String>) {
+    scope: &HashMap<String, SvValue>,
+    context: &EvalContext,
+) -> (String, Option<String>, Option<Unresolved>) {
     let (expr, _) = parse_expression(syntax_tree, x);
     if expr == "unknown" {
-        return ("unknown".to_string(), None);
+        let (source, line) = context.location(&x.nodes.0.nodes.0.nodes.0);
+        return ("unknown".to_string(), None, Some(Unresolved::new(source, line, None, None)));
     }
 
-    let width_bits = compute_packed_range_width_bits(&expr, defines)
-        .map(|w| w.to_string())
-        .unwrap_or_else(|| expr.clone());
-
-    (width_bits, Some(expr))
-}
-
-fn compute_packed_range_width_bits(expr: &str, defines: &HashMap<String, String>) -> Option<i64> {
-    // Expect forms like:
-    // - "[MSB:LSB]"  => width = abs(MSB-LSB)+1
-    // - "[N]"        => width = N+1? (SV single bit select is 1 bit; keep conservative and return None)
-    let mut s = expr.trim();
-    if !s.starts_with('[') || !s.ends_with(']') {
-        return None;
-    }
-    s = &s[1..s.len() - 1];
-
-    let mut parts = s.splitn(2, ':');
-    let left = parts.next()?.trim();
-    let right = parts.next().map(str::trim);
-
-    // Only handle real ranges with ':'
-    let right = right?;
-    let msb = eval_int_expr(left, defines)?;
-    let lsb = eval_int_expr(right, defines)?;
-
-    Some((msb - lsb).abs() + 1)
-}
-
-fn create_sv_engine() -> Engine {
-    let mut engine = Engine::new();
-
-    engine.register_fn("clog2", |n: i64| {
-        if n <= 1 {
-            0i64
-        } else {
-            (n as f64).log2().ceil() as i64
+    // [MSB:LSB] spans abs(MSB - LSB) + 1 bits
+    let range = &x.nodes.0.nodes.1;
+    let evaluator = context.evaluator(scope);
+    let mut reason = None;
+    let mut bound = |x| {
+        let value = evaluator.eval(x)?;
+        if value.to_bigint().is_none() {
+            reason = Some(format!("a bound evaluates to {}", value));
         }
-    });
-
-    engine.register_fn("pow", |base: i64, exp: i64| {
-        if exp < 0 { return 0i64; }
-        base.pow(exp as u32)
-    });
-
-    engine
-}
-
-fn preprocess_for_rhai(input: &str) -> String {
-    let mut s = parse_sv_number(input);
-    s = s.replace("**", " `pow` ");
-    s = s.replace("$clog2", "clog2");
-    s
-}
-
-fn eval_int_expr(input: &str, defines: &HashMap<String, String>) -> Option<i64> {
-    let engine = create_sv_engine();
-    let mut scope = Scope::new();
-
-    for (key, value) in defines {
-        let clean_val = preprocess_for_rhai(value);
-        if let Ok(v) = engine.eval_expression_with_scope::<i64>(&mut scope, &clean_val) {
-            scope.push(key.clone(), v);
+        value.to_bigint()
+    };
+    let (msb, lsb) = (bound(&range.nodes.0), bound(&range.nodes.2));
+    match (msb, lsb) {
+        (Some(msb), Some(lsb)) => (((msb - lsb).abs() + 1u32).to_string(), Some(expr), None),
+        _ => {
+            let reason = reason.or_else(|| evaluator.error());
+            let (source, line) = context.location(&x.nodes.0.nodes.0.nodes.0);
+            (expr.clone(), Some(expr), Some(Unresolved::new(source, line, evaluator.missing(), reason)))
         }
     }
+}
 
-    let clean_input = preprocess_for_rhai(input);
-
-    match engine.eval_expression_with_scope::<i64>(&mut scope, &clean_input) {
-        Ok(res) => Some(res),
-        Err(e) => {
-            eprintln!("Evaluation error: {}", e);
-            None
+/// `posedge`/`negedge` signals of the event control starting an `always`.
+fn parse_edge_events(syntax_tree: &SyntaxTree, x: &sv_parser::AlwaysConstruct) -> Vec<EdgeEvent> {
+    let mut events = Vec::new();
+    let Some(event_control) = unwrap_node!(x, EventControl) else {
+        return events;
+    };
+    for node in event_control {
+        if let RefNode::EventExpressionExpression(x) = node {
+            let (Some(edge), Some(signal)) = (&x.nodes.0, unwrap_node!(&x.nodes.1, SimpleIdentifier, EscapedIdentifier)) else {
+                continue;
+            };
+            let edge = get_identifier(RefNode::EdgeIdentifier(edge)).and_then(|id| syntax_tree.get_str(&id));
+            let signal = get_identifier(signal).and_then(|id| syntax_tree.get_str(&id));
+            if let (Some(edge), Some(signal)) = (edge, signal) {
+                events.push(EdgeEvent::new(signal, edge));
+            }
         }
     }
+    events
 }
 
-fn parse_sv_number(lit: &str) -> String {
-    lazy_static! {
-        static ref SV_NUM_RE: Regex = Regex::new(r"(?i)(\d+)?'([sdhbo])([0-9a-f_xz]+)").unwrap();
-    }
-
-    SV_NUM_RE.replace_all(lit, |caps: &Captures| {
-        let base = caps[2].to_ascii_lowercase();
-        let digits = caps[3].replace('_', "");
-
-        let parsed_val = match base.as_str() {
-            "h" => i64::from_str_radix(&digits, 16),
-            "b" => i64::from_str_radix(&digits, 2),
-            "o" => i64::from_str_radix(&digits, 8),
-            "d" | "s" => digits.parse::<i64>(),
-            _ => return "unknown".to_string(),
-        };
-
-        parsed_val.map(|v| v.to_string()).unwrap_or_else(|_| "unknown".to_string())
-    }).to_string()
+/// File `locate` comes from, which may be an included file.
+fn source_of(syntax_tree: &SyntaxTree, locate: &Locate) -> Option<String> {
+    syntax_tree.get_origin(locate).map(|(path, _)| path.to_string_lossy().into_owned())
 }
 
 pub fn get_identifier(node: RefNode) -> Option<Locate> {
     // unwrap_node! can take multiple types
     match unwrap_node!(node, SimpleIdentifier, EscapedIdentifier, Keyword) {
         Some(RefNode::SimpleIdentifier(x)) => {
-            return Some(x.nodes.0);
+            Some(x.nodes.0)
         }
         Some(RefNode::EscapedIdentifier(x)) => {
-            return Some(x.nodes.0);
+            Some(x.nodes.0)
         }
         Some(RefNode::Keyword(x)) => {
-            return Some(x.nodes.0);
+            Some(x.nodes.0)
         }
         _ => None,
     }
 }
 
-pub fn parse_file(path: &PathBuf) -> Result<HdlInfo, std::io::Error> {
-    let defines = HashMap::new();
-    let includes: Vec<PathBuf> = Vec::new();
+/// Parse `path` as a compilation unit of its own, with `parameters`
+/// overriding the defaults of the module parameters of the same name.
+#[cfg(test)]
+pub fn parse_file(path: &PathBuf, parameters: &HashMap<String, String>) -> Result<HdlInfo, std::io::Error> {
+    CompilationUnit::builder().parse_file(path, parameters)
+}
+
+/// Read a `-f` filelist: one source file per line, plus `+incdir+DIR` and
+/// `+define+NAME[=VALUE]` options and `//` or `#` comments. Relative paths are
+/// resolved against the directory of the filelist. Unsupported options are
+/// returned as diagnostics of an otherwise empty `HdlInfo`.
+pub fn read_filelist(path: &Path) -> Result<(Vec<PathBuf>, CompilationUnit, HdlInfo), std::io::Error> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut files = Vec::new();
+    let mut unit = CompilationUnit::builder();
+    let mut hdl_info = HdlInfo::new();
 
-    // Parse
-    let result = parse_sv(&path, &defines, &includes, false, false);
-    match result {
-        Ok((syntax_tree, _)) => parse_module(&syntax_tree),
-        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, format!("parse_file failed [{}]: {}", path.display(), e)))
+    for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.split("//").next().unwrap().trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(dirs) = line.strip_prefix("+incdir+") {
+            for dir in dirs.split('+').filter(|d| !d.is_empty()) {
+                unit = unit.include_dir(base.join(dir));
+            }
+        } else if let Some(defines) = line.strip_prefix("+define+") {
+            for define in defines.split('+').filter(|d| !d.is_empty()) {
+                let (name, value) = define.split_once('=').unwrap_or((define, ""));
+                unit = unit.define(name, value);
+            }
+        } else if line.starts_with(['+', '-']) {
+            let source = Some(path.display().to_string());
+            hdl_info.add_diagnostic(source, number as u32 + 1, &format!("unsupported filelist option {}", line));
+        } else {
+            files.push(base.join(line));
+        }
     }
+    Ok((files, unit, hdl_info))
 }
 
 #[cfg(test)]
@@ -357,11 +1052,426 @@ mod tests {
             .join("sv")
             .join("basic_module.sv");
 
-        let hdl_info = parse_file(&path).expect("parse_file failed");
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
 
         // 基本正确性检查：模块名和端口数量
         assert!(!hdl_info.get_modules().is_empty());
         println!("Module: {:#?}", hdl_info);
     }
-}
 
+    #[test]
+    fn parse_non_ansi_module_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("non_ansi_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        let ports = info["modules"][0]["ports"].as_array().unwrap();
+
+        // 端口顺序跟随模块头，类型和位宽从 reg/wire 声明合并
+        let names: Vec<&str> = ports.iter().map(|p| p["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["clk", "data_out", "data_in", "valid", "pad"]);
+        assert_eq!(ports[1]["direction"], "output");
+        assert_eq!(ports[1]["port_type"], "reg");
+        assert_eq!(ports[1]["width"], "8");
+        assert_eq!(ports[3]["port_type"], "reg");
+        assert_eq!(ports[3]["width"], "1");
+        // 函数和任务内部的同名声明不影响端口
+        assert_eq!(ports[4]["port_type"], "wire");
+        assert_eq!(ports[4]["width"], "1");
+    }
+
+    #[test]
+    fn parse_ansi_inherit_module_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("ansi_inherit_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+
+        // 省略方向和类型的端口继承前一个端口的方向、类型和位宽
+        let ports = info["modules"][0]["ports"].as_array().unwrap();
+        assert_eq!(ports[1]["name"], "b");
+        assert_eq!(ports[1]["direction"], "input");
+        assert_eq!(ports[1]["port_type"], "logic");
+        assert_eq!(ports[1]["width"], "8");
+        assert_eq!(ports[3]["direction"], "output");
+        assert_eq!(ports[3]["width"], "1");
+        assert_eq!(ports[5]["port_type"], "logic");
+
+        // 继承不跨越模块头，首个端口默认为 inout
+        let ports = info["modules"][1]["ports"].as_array().unwrap();
+        assert_eq!(ports[0]["direction"], "inout");
+        assert_eq!(ports[1]["direction"], "inout");
+        assert_eq!(ports[1]["width"], "2");
+    }
+
+    #[test]
+    fn parse_hierarchy_top_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("hierarchy_top.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+
+        // 实例名、模块类型、参数覆盖和端口连接
+        let instances = info["modules"][1]["instances"].as_array().unwrap();
+        assert_eq!(instances[0]["name"], "u_leaf");
+        assert_eq!(instances[0]["module_type"], "hierarchy_leaf");
+        assert_eq!(instances[0]["parameters"][0]["name"], "WIDTH");
+        assert_eq!(instances[0]["parameters"][0]["value"], "4");
+        assert_eq!(instances[1]["connections"][1]["port"], serde_json::Value::Null);
+        assert_eq!(instances[1]["connections"][1]["expression"], "d[0]");
+        assert_eq!(instances[1]["connections"][2]["expression"], serde_json::Value::Null);
+
+        let instances = info["modules"][2]["instances"].as_array().unwrap();
+        assert_eq!(instances[0]["connections"][0]["expression"], "clk");
+        assert_eq!(instances[1]["connections"][0]["port"], "*");
+
+        // 层次树：未提取的模块作为叶子
+        assert_eq!(hdl_info.top_modules(), ["hierarchy_top"]);
+        let hierarchy = hdl_info.hierarchy("hierarchy_top").unwrap();
+        assert_eq!(hierarchy.to_string(), indoc::indoc! {"
+            hierarchy_top (hierarchy_top)
+              u_mid0 (hierarchy_mid)
+                u_leaf (hierarchy_leaf)
+                u_sync (vendor_sync_cell)
+              u_mid1 (hierarchy_mid)
+                u_leaf (hierarchy_leaf)
+                u_sync (vendor_sync_cell)
+        "});
+        assert!(hdl_info.hierarchy("vendor_sync_cell").is_none());
+    }
+
+    #[test]
+    fn parse_generate_module_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("generate_module.sv");
+
+        let instance_names = |hdl_info: &HdlInfo| {
+            let info = serde_json::to_value(hdl_info).unwrap();
+            info["modules"][1]["instances"].as_array().unwrap().iter()
+                .map(|i| i["name"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        // 按参数默认值展开 generate for/if/case
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        assert_eq!(instance_names(&hdl_info), [
+            "g_lane[0].u_lane", "g_lane[1].u_lane", "g_lane[2].u_lane",
+            "g_lane[2].g_last.u_tail", "g_sync.u_sync", "g_mode12.u_mode",
+        ]);
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        assert_eq!(info["modules"][1]["ports"][1]["width"], "24");
+        assert_eq!(info["modules"][1]["parameters"][3]["name"], "LAST");
+        assert_eq!(info["modules"][1]["parameters"][3]["value"], "2");
+
+        // 参数覆盖选择另一组分支
+        let parameters = HashMap::from([
+            ("LANES".to_string(), "1".to_string()),
+            ("USE_SYNC".to_string(), "0".to_string()),
+            ("MODE".to_string(), "7".to_string()),
+        ]);
+        let hdl_info = parse_file(&path, &parameters).expect("parse_file failed");
+        assert_eq!(instance_names(&hdl_info), [
+            "g_lane[0].u_lane", "g_lane[0].g_last.u_tail", "g_nosync.u_bypass", "g_mode_default.u_mode",
+        ]);
+
+        // 只合并被展开分支中的声明，generate 块和函数内的参数不属于模块
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        let module = &info["modules"][2];
+        assert_eq!(module["ports"][1]["port_type"], "reg");
+        assert_eq!(module["ports"][1]["width"], "1");
+        let names = module["parameters"].as_array().unwrap().iter().map(|p| p["name"].as_str().unwrap()).collect::<Vec<&str>>();
+        assert_eq!(names, ["WIDE"]);
+        let hdl_info = parse_file(&path, &HashMap::from([("WIDE".to_string(), "1".to_string())])).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        assert_eq!(info["modules"][2]["ports"][1]["width"], "8");
+
+        // 超过迭代上限的循环保留为符号作用域并给出诊断
+        let instances = info["modules"][2]["instances"].as_array().unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0]["name"], "g_huge[j].u_cell");
+        assert_eq!(info["diagnostics"][0]["message"], "generate loop g_huge exceeds 4096 iterations and is kept as g_huge[j]");
+    }
+
+    #[test]
+    fn parse_wide_module_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("wide_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        let module = &info["modules"][0];
+
+        // 超过 64 位的字面量与参数不再退化为 unknown
+        assert_eq!(module["parameters"][0]["value"], "18446744073709551616");
+        assert_eq!(module["parameters"][1]["value"], "65");
+        assert_eq!(module["ports"][0]["width"], "16");
+        assert_eq!(module["ports"][1]["width"], "65");
+        // x/z 位保留在参数值中，宽度退回表达式
+        assert_eq!(module["parameters"][2]["value"], "4'b10x1");
+        assert_eq!(module["ports"][2]["width"], "[UNKNOWN:0]");
+    }
+
+    #[test]
+    fn parse_sysfunc_module_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("sysfunc_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        let module = &info["modules"][0];
+
+        // 参数值按声明类型转换
+        assert_eq!(module["parameters"][1]["value"], "182");
+        assert_eq!(module["parameters"][2]["value"], "250.0");
+
+        // $bits/$size 通过 typedef 与结构体求宽度
+        let widths: Vec<&str> = module["ports"].as_array().unwrap().iter()
+            .map(|p| p["width"].as_str().unwrap())
+            .collect();
+        assert_eq!(widths, ["13", "52", "4", "2", "8", "8", "5", "3", "16", "4"]);
+    }
+
+    #[test]
+    fn parse_define_module_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("define_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+
+        // 宏按源码顺序求值，引用的宏使用最终定义，`undef 后移除
+        let defines: Vec<(&str, &serde_json::Value)> = info["defines"].as_array().unwrap().iter()
+            .map(|d| (d["name"].as_str().unwrap(), &d["evaluated"]))
+            .collect();
+        assert_eq!(defines, [
+            ("DEPTH", &serde_json::json!("32")),
+            ("WIDTH", &serde_json::json!("16")),
+            ("ADDR_W", &serde_json::json!("5")),
+            ("MAX", &serde_json::Value::Null),
+            ("CLAMP", &serde_json::Value::Null),
+        ]);
+
+        // 值不同的重定义和未定义宏的 `undef 产生告警
+        let diagnostics: Vec<u64> = info["diagnostics"].as_array().unwrap().iter()
+            .map(|d| d["line"].as_u64().unwrap())
+            .collect();
+        assert_eq!(diagnostics, [4, 8]);
+
+        let module = &info["modules"][0];
+        assert_eq!(module["parameters"][1]["value"], "5");
+        assert_eq!(module["ports"][0]["width"], "16");
+        assert_eq!(module["ports"][1]["width"], "5");
+    }
+
+    #[test]
+    fn parse_compilation_unit_filelist() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("unit")
+            .join("unit.f");
+
+        let (files, mut unit, filelist) = read_filelist(&path).expect("read_filelist failed");
+        assert_eq!(files.len(), 2);
+
+        // 不支持的选项作为 filelist 的告警
+        let diagnostics = filelist.get_diagnostics().iter().map(|d| d.to_string()).collect::<Vec<String>>();
+        assert_eq!(diagnostics, [format!("{}:3: unsupported filelist option -timescale=1ns/1ps", path.display())]);
+
+        // 单独解析时看不到 defs.svh 中的宏
+        assert!(unit.clone().parse_file(&files[1], &HashMap::new()).is_err());
+
+        // 同一编译单元中宏按文件顺序传递
+        let defs = unit.parse_file(&files[0], &HashMap::new()).expect("parse_file failed");
+        assert!(defs.get_modules().is_empty());
+        let hdl_info = unit.parse_file(&files[1], &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+
+        let module = &info["modules"][0];
+        assert_eq!(module["parameters"][0]["value"], "2");
+        assert_eq!(module["ports"][0]["width"], "32");
+        assert_eq!(module["ports"][1]["width"], "4");
+
+        let defines: Vec<(&str, &str)> = info["defines"].as_array().unwrap().iter()
+            .map(|d| (d["name"].as_str().unwrap(), d["evaluated"].as_str().unwrap()))
+            .collect();
+        assert_eq!(defines, [("LANES", "2"), ("BUS_W", "64"), ("BYTES", "8")]);
+        assert_eq!(info["diagnostics"][0]["line"], 9);
+
+        // 各文件单独解析时都带有 +define+ 的宏，合并后只保留一次
+        let (_, unit, _) = read_filelist(&path).expect("read_filelist failed");
+        let mut merged = HdlInfo::new();
+        for file in [files[0].clone(), path.with_file_name("..").join("basic_module.sv")] {
+            merged.merge_info(&unit.clone().parse_file(&file, &HashMap::new()).expect("parse_file failed"));
+        }
+        let names = merged.get_defines().iter().map(|d| d.get_name()).filter(|n| n == "LANES").count();
+        assert_eq!(names, 1);
+    }
+
+    #[test]
+    fn parse_unresolved_module_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("sv")
+            .join("unresolved_module.sv");
+
+        let hdl_info = parse_file(&path, &HashMap::new()).expect("parse_file failed");
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        let ports = &info["modules"][0]["ports"];
+
+        // 无法求值的宽度记录文件、行号和未解析的标识符
+        let source = path.to_string_lossy();
+        assert_eq!(ports[0]["unresolved"], serde_json::json!({"source": source, "line": 4, "identifier": "BUS_W", "reason": null}));
+        assert_eq!(ports[1]["unresolved"], serde_json::json!({"source": source, "line": 12, "identifier": "DEPTH", "reason": null}));
+        assert!(ports[2]["unresolved"].is_null());
+        assert_eq!(ports[2]["width"], "4");
+
+        let diagnostics: Vec<&str> = info["diagnostics"].as_array().unwrap().iter()
+            .map(|d| d["message"].as_str().unwrap())
+            .collect();
+        assert_eq!(diagnostics, [
+            "width [BUS_W-1:0] of port a is unresolved, `BUS_W` not found",
+            "width [DEPTH:0] of port b is unresolved, `DEPTH` not found",
+            "width [1'bx:0] of port d is unresolved, a bound evaluates to 1'hx",
+            "width [2.5%2:0] of port e is unresolved, operator % is not defined for its operands",
+            "width [DW-1:0] of port f is unresolved, `DW` not found",
+            "width [8*UNDEF_P-1:0] of port g is unresolved, `UNDEF_P` not found",
+        ]);
+
+        // 合并多个文件后诊断仍带有来源文件
+        let mut merged = HdlInfo::new();
+        merged.merge_info(&hdl_info);
+        assert!(merged.get_diagnostics().iter().all(|d| d.get_source().as_deref() == Some(&*source)));
+        assert_eq!(merged.get_diagnostics()[0].to_string(), format!("{}:4: width [BUS_W-1:0] of port a is unresolved, `BUS_W` not found", source));
+    }
+
+    #[test]
+    fn keep_range_widths() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sv");
+        // 求值器替换前后位宽一致；旧引擎忽略字面量位宽，8'd512 截断为 0，减 1 后为 32 位无符号的全 1
+        let widths = [
+            ("ansi_port_module.sv", "ansi_port_module", "sum", "10"),
+            ("basic_module.sv", "basic_module", "data_sv_h", "172"),
+            ("basic_module.sv", "basic_module", "data_sv_d", "4294967296"),
+            ("basic_module.sv", "basic_module", "data_sv_b", "40"),
+            ("basic_module.sv", "basic_module", "data_sv_shift", "4096"),
+            ("basic_module.sv", "basic_module", "data_sv_mul_div", "128"),
+            ("define_module.sv", "define_module", "addr_o", "5"),
+            ("generate_module.sv", "generate_lane", "d", "8"),
+            ("generate_module.sv", "generate_module", "q", "24"),
+            ("hierarchy_top.sv", "hierarchy_leaf", "q", "8"),
+            ("package_module.sv", "package_module", "lane_i", "3"),
+            ("wide_module.sv", "wide_module", "seed", "65"),
+        ];
+        for (file, module, port, width) in widths {
+            let hdl_info = parse_file(&root.join(file), &HashMap::new()).expect("parse_file failed");
+            let port = hdl_info.get_module(module).unwrap().get_ports().iter().find(|p| p.get_name() == port).unwrap();
+            assert_eq!(port.get_width(), width, "{} of {}", port.get_name(), module);
+        }
+    }
+
+    /// 生成的大规模 RTL 语料，用于对比求值开销
+    fn generated_corpus(defines: usize, modules: usize, ports: usize) -> String {
+        let mut source = String::new();
+        for i in 0..defines {
+            source += &format!("`define CORPUS_W{} ({} + {} * 2)\n", i, i % 7, i % 13);
+        }
+        for m in 0..modules {
+            source += &format!("module corpus_{} #(\n    parameter LANES = {},\n", m, m % 8 + 1);
+            for p in 0..16 {
+                source += &format!("    parameter P{} = LANES * {} + `CORPUS_W{},\n", p, p + 1, (m + p) % defines);
+            }
+            source += "    parameter LAST = $clog2(P15 + 1)\n) (\n";
+            for p in 0..ports {
+                let sep = if p + 1 == ports { "" } else { "," };
+                source += &format!("    input logic [`CORPUS_W{} + P{} - 1:0] port_{}{}\n", (m * ports + p) % defines, p % 16, p, sep);
+            }
+            source += ");\n    for (genvar i = 0; i < LANES * 4; i++) begin : g_lane\n";
+            source += "        if (i % 2 == 0) begin : g_even\n            corpus_leaf u_leaf (.a(port_0));\n        end\n    end\nendmodule\n";
+        }
+        source
+    }
+
+    /// cargo test --release -- --ignored --nocapture bench_generated_corpus
+    #[test]
+    #[ignore]
+    fn bench_generated_corpus() {
+        let source = generated_corpus(500, 200, 64);
+        let (syntax_tree, _) = sv_parser::parse_sv_str(&source, PathBuf::from("corpus.sv"), &HashMap::new(), &Vec::<PathBuf>::new(), false, false)
+            .expect("corpus parse failed");
+
+        let start = std::time::Instant::now();
+        let hdl_info = parse_module(&syntax_tree, &HashMap::new(), &mut CompilationUnit::default()).unwrap();
+        println!("parse_module: {:?}", start.elapsed());
+
+        let info = serde_json::to_value(&hdl_info).unwrap();
+        assert_eq!(info["modules"].as_array().unwrap().len(), 200);
+        assert_eq!(info["modules"][0]["ports"][0]["width"], "1");
+
+        // 每次求值新建上下文（共享前的做法）与共享的记忆化上下文对比：
+        // 求值所有端口范围和 define 的值
+        let source = generated_corpus(100, 20, 8);
+        let (syntax_tree, _) = sv_parser::parse_sv_str(&source, PathBuf::from("corpus.sv"), &HashMap::new(), &Vec::<PathBuf>::new(), false, false)
+            .expect("corpus parse failed");
+        let hdl_info = parse_module(&syntax_tree, &HashMap::new(), &mut CompilationUnit::default()).unwrap();
+        let defines = hdl_info.get_defines().iter().map(|d| (d.get_name(), d.get_value())).collect::<Vec<(String, String)>>();
+        let scopes = hdl_info.get_modules().iter()
+            .map(|m| m.get_parameters().iter().filter_map(|p| Some((p.get_name(), eval_str(&p.get_value(), &HashMap::new())?))).collect())
+            .collect::<Vec<HashMap<String, SvValue>>>();
+        let mut ranges = Vec::new();
+        let modules = syntax_tree.into_iter().filter_map(|node| match node {
+            RefNode::ModuleDeclaration(x) => Some(x),
+            _ => None,
+        });
+        for (index, module) in modules.enumerate() {
+            for node in module {
+                if let RefNode::PackedDimensionRange(x) = node {
+                    ranges.push((index, &x.nodes.0.nodes.1));
+                }
+            }
+        }
+        let new_context = || {
+            let mut context = EvalContext::new(&syntax_tree, parse_typedefs(&syntax_tree));
+            defines.iter().for_each(|(name, body)| context.add_define(name, body));
+            context
+        };
+        let evaluate_range = |context: &EvalContext, (index, range): &(usize, &sv_parser::ConstantRange)| {
+            let evaluator = context.evaluator(&scopes[*index]);
+            (evaluator.eval(&range.nodes.0).map(|v| v.to_string()), evaluator.eval(&range.nodes.2).map(|v| v.to_string()))
+        };
+
+        let start = std::time::Instant::now();
+        let fresh_ranges = ranges.iter().map(|r| evaluate_range(&new_context(), r)).collect::<Vec<_>>();
+        let fresh_defines = defines.iter().map(|(name, _)| new_context().define_value(name).map(|v| v.to_string())).collect::<Vec<_>>();
+        let fresh = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let context = new_context();
+        let shared_ranges = ranges.iter().map(|r| evaluate_range(&context, r)).collect::<Vec<_>>();
+        let shared_defines = defines.iter().map(|(name, _)| context.define_value(name).map(|v| v.to_string())).collect::<Vec<_>>();
+        let shared = start.elapsed();
+
+        assert_eq!(fresh_ranges, shared_ranges);
+        assert_eq!(fresh_defines, shared_defines);
+        let ratio = fresh.as_secs_f64() / shared.as_secs_f64();
+        println!("{} ranges, {} defines: fresh context {:?}, shared context {:?} ({:.1}x)", ranges.len(), defines.len(), fresh, shared, ratio);
+        assert!(ratio > 2.0);
+    }
+}
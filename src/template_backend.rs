@@ -0,0 +1,145 @@
+use std::{error::Error, fs, path::Path};
+
+use minijinja::{Environment, Value, context};
+
+use crate::backend::Backend;
+use crate::converter::write_to_file;
+use crate::hdl_info::{HdlInfo, Module};
+
+/// Backend rendering a directory of user templates (Jinja2/Tera syntax).
+/// `module.<ext>.j2` is rendered for every module as `<module>.<ext>` with
+/// `module` and the whole `hdl_info`. Any other `<name>.j2` is rendered once
+/// with `hdl_info` as `<name>`. Templates starting with `_` are only included.
+pub struct TemplateBackend {
+    name: String,
+    extension: String,
+    env: Environment<'static>,
+    shared: Vec<String>,
+}
+
+const MODULE_TEMPLATE: &str = "module.";
+const TEMPLATE_SUFFIX: &str = ".j2";
+
+impl TemplateBackend {
+    /// Load every template of `dir`. The backend is named after the directory.
+    pub fn from_dir(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut env = Environment::new();
+        env.set_keep_trailing_newline(true);
+        let mut extension = None;
+        let mut shared = Vec::new();
+
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let Some(name) = file_name.strip_suffix(TEMPLATE_SUFFIX) else {
+                continue;
+            };
+            if let Some(ext) = name.strip_prefix(MODULE_TEMPLATE) {
+                extension = Some(ext.to_string());
+            } else if !name.starts_with('_') {
+                shared.push(name.to_string());
+            }
+            env.add_template_owned(file_name.clone(), fs::read_to_string(entry.path())?)
+                .map_err(|e| format!("Template {}: {}", entry.path().display(), e))?;
+        }
+
+        let Some(extension) = extension else {
+            return Err(format!("Template directory {} has no {}<ext>{}", dir.display(), MODULE_TEMPLATE, TEMPLATE_SUFFIX).into());
+        };
+        let name = dir.file_name().map_or("template".into(), |n| n.to_string_lossy().to_string());
+        Ok(TemplateBackend { name, extension, env, shared })
+    }
+
+    /// Render `template`, naming the `target` being rendered in the error.
+    fn render(&self, template: &str, target: &str, context: Value) -> Result<String, Box<dyn Error>> {
+        self.env.get_template(template)
+            .and_then(|t| t.render(context))
+            .map_err(|e| format!("Failed to render {} with template {}: {:#}", target, self.name, e).into())
+    }
+
+    fn render_module(&self, module: &Module, hdl_info: &HdlInfo) -> Result<String, Box<dyn Error>> {
+        let template = format!("{}{}{}", MODULE_TEMPLATE, self.extension, TEMPLATE_SUFFIX);
+        self.render(&template, &module.get_name(), context! { module => module, hdl_info => hdl_info })
+    }
+
+    fn render_shared(&self, name: &str, hdl_info: &HdlInfo) -> Result<String, Box<dyn Error>> {
+        self.render(&format!("{}{}", name, TEMPLATE_SUFFIX), name, context! { hdl_info => hdl_info })
+    }
+}
+
+impl Backend for TemplateBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn file_extension(&self) -> &str {
+        &self.extension
+    }
+
+    fn emit_module(&self, module: &Module, hdl_info: &HdlInfo) -> Result<String, Box<dyn Error>> {
+        self.render_module(module, hdl_info)
+    }
+
+    /// Shared templates keep their own extension, so they are written here
+    /// rather than through `emit_shared`.
+    fn emit(&self, output: &Path, hdl_info: &HdlInfo) -> Result<(), Box<dyn Error>> {
+        let path = output.join(self.directory());
+        for module in hdl_info.get_modules() {
+            write_to_file(&path, &module.get_name(), &self.render_module(module, hdl_info)?, &self.extension);
+        }
+        for name in &self.shared {
+            let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+            write_to_file(&path, stem, &self.render_shared(name, hdl_info)?, extension);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, path::PathBuf};
+    use crate::sv_parse::parse_file;
+
+    #[test]
+    fn render_template_directory() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let backend = TemplateBackend::from_dir(&root.join("templates").join("docs")).expect("from_dir failed");
+        assert_eq!(backend.name(), "docs");
+        assert_eq!(backend.file_extension(), "md");
+        assert_eq!(backend.shared, ["index.md"]);
+
+        let hdl_info = parse_file(&root.join("sv").join("package_module.sv"), &HashMap::new()).expect("parse_file failed");
+
+        // 每个模块渲染 module 模板，可 include 以 _ 开头的模板
+        assert_eq!(backend.emit_module(&hdl_info.get_modules()[0], &hdl_info).unwrap(), indoc::indoc! {"
+            # package_module
+
+            | Parameter | Value |
+            |---|---|
+            | LANES | 3 |
+
+            | Port | Direction | Width |
+            |---|---|---|
+            | data_i | input | 32 |
+            | strb_i | input | 4 |
+            | lane_i | input | 3 |
+
+        "});
+
+        // 其余模板针对整个设计渲染一次
+        assert_eq!(backend.render_shared("index.md", &hdl_info).unwrap(), indoc::indoc! {"
+            # Modules
+
+            - [package_module](package_module.md) (3 ports)
+
+        "});
+
+        // 渲染失败时返回错误，不写出空文件
+        let mut broken = TemplateBackend { shared: Vec::new(), ..backend };
+        broken.env.add_template("module.md.j2", "{% include \"missing.j2\" %}").unwrap();
+        let error = broken.emit_module(&hdl_info.get_modules()[0], &hdl_info).unwrap_err();
+        assert!(error.to_string().starts_with("Failed to render package_module with template docs: "), "{}", error);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{error::Error, path::Path};
+
+use rhai::{AST, Array, Dynamic, Engine, Scope};
+
+use crate::hdl_info::{HdlInfo, Module, Port};
+
+/// User script run on every module before generation. The script defines
+/// `fn transform(m)` returning the module to keep, or `()` to drop it.
+///
+/// Modules expose `name`, `verilog_name`, `ports` and `annotations`, and can
+/// be changed with `rename_port(from, to)`, `set_port_type(port, type)`,
+/// `group_ports(group, [ports])`, `annotate(line)` and
+/// `annotate_port(port, line)`. Ports expose `name`, `verilog_name`,
+/// `direction`, `width`, `type`, `scala_type`, `group` and `annotations`.
+///
+/// `name` is the Scala name, which setting `name` or `rename_port` changes;
+/// `verilog_name` stays the SV one the generated code binds to.
+pub struct Transform {
+    engine: Engine,
+    ast: AST,
+}
+
+const TRANSFORM_FN: &str = "transform";
+
+impl Transform {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let engine = create_transform_engine();
+        let ast = engine.compile_file(path.to_path_buf())
+            .map_err(|e| format!("Transform script {}: {}", path.display(), e))?;
+        Ok(Transform { engine, ast })
+    }
+
+    #[cfg(test)]
+    fn from_script(script: &str) -> Result<Self, Box<dyn Error>> {
+        let engine = create_transform_engine();
+        let ast = engine.compile(script)?;
+        Ok(Transform { engine, ast })
+    }
+
+    /// Replace the modules of `hdl_info` with the ones the script returns.
+    pub fn apply(&self, hdl_info: &mut HdlInfo) -> Result<(), Box<dyn Error>> {
+        let mut modules = Vec::new();
+        for module in hdl_info.get_modules() {
+            let name = module.get_name();
+            let result: Dynamic = self.engine.call_fn(&mut Scope::new(), &self.ast, TRANSFORM_FN, (module.clone(),))
+                .map_err(|e| format!("Transform of {} failed: {}", name, e))?;
+            if result.is_unit() {
+                continue;
+            }
+            match result.try_cast::<Module>() {
+                Some(module) => modules.push(module),
+                None => return Err(format!("Transform of {} returned neither a module nor ()", name).into()),
+            }
+        }
+        hdl_info.set_modules(modules);
+        Ok(())
+    }
+}
+
+fn create_transform_engine() -> Engine {
+    let mut engine = Engine::new();
+
+    engine.register_type_with_name::<Module>("Module")
+        .register_get_set("name", |m: &mut Module| m.get_scala_name().unwrap_or(m.get_name()), |m: &mut Module, name: String| m.set_scala_name(&name))
+        .register_get("verilog_name", |m: &mut Module| m.get_name())
+        .register_get("ports", |m: &mut Module| m.get_ports().iter().cloned().map(Dynamic::from).collect::<Array>())
+        .register_get("annotations", |m: &mut Module| to_array(m.get_annotations()))
+        .register_fn("rename_port", |m: &mut Module, from: &str, to: &str| m.rename_port(&verilog_port(m, from), to))
+        .register_fn("set_port_type", |m: &mut Module, port: &str, scala_type: &str| m.set_port_type(&verilog_port(m, port), scala_type))
+        .register_fn("group_ports", |m: &mut Module, group: &str, ports: Array| {
+            ports.iter().all(|p| m.set_port_group(&verilog_port(m, &p.to_string()), group))
+        })
+        .register_fn("annotate", |m: &mut Module, annotation: &str| m.add_annotation(annotation))
+        .register_fn("annotate_port", |m: &mut Module, port: &str, annotation: &str| m.add_port_annotation(&verilog_port(m, port), annotation));
+
+    engine.register_type_with_name::<Port>("Port")
+        .register_get("name", |p: &mut Port| p.get_scala_name().unwrap_or(p.get_name()))
+        .register_get("verilog_name", |p: &mut Port| p.get_name())
+        .register_get("direction", |p: &mut Port| p.get_direction())
+        .register_get("width", |p: &mut Port| p.get_width())
+        .register_get("type", |p: &mut Port| p.get_port_type())
+        .register_get("scala_type", |p: &mut Port| p.get_scala_type().map_or(Dynamic::UNIT, Dynamic::from))
+        .register_get("group", |p: &mut Port| p.get_group().map_or(Dynamic::UNIT, Dynamic::from))
+        .register_get("annotations", |p: &mut Port| to_array(p.get_annotations()));
+
+    engine
+}
+
+/// SV name of the port a script refers to by its Scala name.
+fn verilog_port(module: &Module, name: &str) -> String {
+    module.get_ports().iter()
+        .find(|p| p.get_scala_name().unwrap_or(p.get_name()) == name)
+        .map_or(name.to_string(), |p| p.get_name())
+}
+
+fn to_array(lines: &[String]) -> Array {
+    lines.iter().cloned().map(Dynamic::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, path::PathBuf};
+    use crate::sv_parse::parse_file;
+
+    #[test]
+    fn transform_modules_with_script() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let mut hdl_info = parse_file(&root.join("sv").join("hierarchy_top.sv"), &HashMap::new()).expect("parse_file failed");
+        let names = hdl_info.get_modules().iter().map(|m| m.get_name()).collect::<Vec<String>>();
+        let transform = Transform::from_file(&root.join("transform").join("conventions.rhai")).expect("from_file failed");
+        transform.apply(&mut hdl_info).expect("apply failed");
+
+        // 脚本可以删除模块，并修改端口
+        let modules = hdl_info.get_modules();
+        assert_eq!(modules.len(), names.len() - 1);
+        let module = &modules[0];
+        assert_eq!(module.get_annotations(), &["// generated by conventions.rhai"]);
+        let ports = module.get_ports();
+        assert_eq!(ports[0].get_scala_type().as_deref(), Some("Clock"));
+        assert_eq!(ports[0].get_group(), None);
+        // 改名只影响 Scala 名，SV 端口名保留
+        assert_eq!(ports[1].get_name(), "d");
+        assert_eq!(ports[1].get_scala_name().as_deref(), Some("data"));
+        assert_eq!(ports[1].get_group().as_deref(), Some("inputs"));
+        assert_eq!(ports[1].get_annotations(), &["// sampled on clk"]);
+    }
+
+    #[test]
+    fn reject_invalid_transform_result() {
+        let mut hdl_info = HdlInfo::new();
+        hdl_info.add_module("top");
+        let transform = Transform::from_script("fn transform(m) { 1 }").unwrap();
+        let error = transform.apply(&mut hdl_info).unwrap_err();
+        assert_eq!(error.to_string(), "Transform of top returned neither a module nor ()");
+    }
+}
@@ -0,0 +1,22 @@
+
+`timescale 1ns/1ps
+
+module ansi_inherit_module (
+    input  logic [7:0] a, b,
+    output             c, d,
+    input  wire  [3:0] e,
+    output var         f
+);
+
+    assign c = a[0];
+    assign d = b[0];
+    assign f = e[0];
+
+endmodule
+
+// Inherited attributes must not leak across module headers
+module ansi_inherit_second (
+    logic [1:0] g, h
+);
+
+endmodule
@@ -0,0 +1,36 @@
+`timescale 1ns/1ps
+
+module async_reset_module (
+    input  logic       clk,
+    input  logic       rst_n,
+    input  logic       d,
+    output logic       q
+);
+
+    always_ff @(posedge clk or negedge rst_n) begin
+        if (!rst_n)
+            q <= 1'b0;
+        else
+            q <= d;
+    end
+
+endmodule
+
+// Ports of two clock domains, without a body
+module dual_clock_stub (
+    input  logic       clk_a_i,
+    input  logic       rst_a_ni,
+    input  logic       clk_en,
+    input  logic       clk_io,
+    input  logic       rst_io,
+    input  logic [7:0] data_a,
+    output logic [7:0] data_b
+);
+endmodule
+
+module comb_module (
+    input  logic [3:0] a,
+    output logic [3:0] y
+);
+    assign y = ~a;
+endmodule
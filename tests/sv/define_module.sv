@@ -0,0 +1,21 @@
+`define DEPTH `WIDTH * 2
+`define WIDTH 8
+`define ADDR_W $clog2(`DEPTH)
+`define WIDTH 16
+`define WIDTH 16
+`define TMP 1
+`undef TMP
+`undef NEVER_DEFINED
+`define MAX(a, b) ((a) > (b) ? (a) : (b))
+`define CLAMP(v, hi) \
+    ((v) > (hi) ? \
+     (hi) : (v))
+
+module define_module #(
+    parameter DEPTH = `DEPTH,
+    parameter ADDR_W = `ADDR_W
+) (
+    input  logic [`WIDTH-1:0]  data_i,
+    output logic [ADDR_W-1:0]  addr_o
+);
+endmodule
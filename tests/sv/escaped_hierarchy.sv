@@ -0,0 +1,25 @@
+`timescale 1ns/1ps
+
+// Instance names with quotes, and paths that flatten alike (a.b and a__b)
+module escaped_leaf (
+    input  logic s
+);
+endmodule
+
+module escaped_mid (
+    input  logic s
+);
+
+    escaped_leaf b (.s(s));
+
+endmodule
+
+module \escaped"top  (
+    input  logic s
+);
+
+    escaped_mid  a (.s(s));
+    escaped_leaf a__b (.s(s));
+    escaped_leaf \u"q<1>  (.s(s));
+
+endmodule
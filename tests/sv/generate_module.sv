@@ -0,0 +1,81 @@
+
+`timescale 1ns/1ps
+
+module generate_lane #(
+    parameter WIDTH = 8
+) (
+    input  logic [WIDTH-1:0] d,
+    output logic [WIDTH-1:0] q
+);
+
+    assign q = d;
+
+endmodule
+
+module generate_module #(
+    parameter LANES    = 3,
+    parameter USE_SYNC = 1,
+    parameter MODE     = 2
+) (
+    input  logic                   clk,
+    input  logic [LANES*8-1:0]     d,
+    output logic [LANES*8-1:0]     q
+);
+
+    localparam LAST = LANES - 1;
+
+    genvar i;
+    generate
+        for (i = 0; i < LANES; i = i + 1) begin : g_lane
+            generate_lane u_lane (.d(d[i*8 +: 8]), .q(q[i*8 +: 8]));
+            if (i == LAST) begin : g_last
+                generate_lane #(.WIDTH(1)) u_tail (.d(d[0]), .q());
+            end
+        end
+    endgenerate
+
+    if (USE_SYNC) begin : g_sync
+        vendor_sync_cell u_sync (.clk(clk));
+    end else begin : g_nosync
+        vendor_bypass_cell u_bypass (.clk(clk));
+    end
+
+    case (MODE)
+        0: begin : g_mode0
+            vendor_mode0_cell u_mode ();
+        end
+        1, 2: begin : g_mode12
+            vendor_mode12_cell u_mode ();
+        end
+        default: begin : g_mode_default
+            vendor_default_cell u_mode ();
+        end
+    endcase
+
+endmodule
+
+// Declarations in generate blocks and functions are not module items
+module generate_scope_module (sel, y);
+
+    parameter WIDE = 0;
+    input  sel;
+    output y;
+
+    if (WIDE) begin : g_wide
+        localparam BLOCK_W = 8;
+        reg [7:0] y;
+    end else begin : g_narrow
+        reg y;
+    end
+
+    function automatic [3:0] invert(input [3:0] v);
+        localparam MASK = 4'hf;
+        invert = v ^ MASK;
+    endfunction
+
+    genvar j;
+    for (j = 0; j < 5000; j = j + 1) begin : g_huge
+        vendor_cell u_cell ();
+    end
+
+endmodule
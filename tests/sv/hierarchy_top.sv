@@ -0,0 +1,36 @@
+
+`timescale 1ns/1ps
+
+module hierarchy_leaf #(
+    parameter WIDTH = 8
+) (
+    input  logic [WIDTH-1:0] d,
+    output logic [WIDTH-1:0] q
+);
+
+    assign q = d;
+
+endmodule
+
+module hierarchy_mid (
+    input  logic       clk,
+    input  logic [3:0] d,
+    output logic [3:0] q
+);
+
+    hierarchy_leaf #(.WIDTH(4)) u_leaf (.d(d), .q(q));
+    vendor_sync_cell u_sync (clk, d[0], );
+
+endmodule
+
+module hierarchy_top (
+    input  logic       clk,
+    input  logic [3:0] d,
+    output logic [3:0] q0,
+    output logic [3:0] q1
+);
+
+    hierarchy_mid u_mid0 (.clk, .d(d), .q(q0));
+    hierarchy_mid u_mid1 (.*, .q(q1));
+
+endmodule
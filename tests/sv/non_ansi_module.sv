@@ -0,0 +1,42 @@
+
+`timescale 1ns/1ps
+
+module non_ansi_module (clk, data_out, data_in, valid, pad);
+
+    output [7:0] data_out;
+    input        clk;
+    input  [7:0] data_in;
+    output       valid;
+    inout        pad;
+
+    reg [7:0] data_out;
+    reg       valid;
+    wire      pad;
+
+    // Registered pass-through
+    always @(posedge clk) begin
+        data_out <= data_in;
+        valid    <= 1'b1;
+    end
+
+    assign pad = 1'bz;
+
+    // Declarations of functions and tasks are not module ports
+    function [15:0] widen;
+        input [3:0] data_in;
+        reg   [15:0] valid;
+        begin
+            valid = {12'b0, data_in};
+            widen = valid;
+        end
+    endfunction
+
+    task pulse;
+        output [3:0] data_out;
+        reg    [3:0] pad;
+        begin
+            data_out = 4'hf;
+        end
+    endtask
+
+endmodule
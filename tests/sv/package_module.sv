@@ -0,0 +1,23 @@
+package cfg_pkg;
+    parameter int DATA_W = 32;
+    localparam int STRB_W = DATA_W / 8;
+    localparam logic [3:0] MODE = 4'b10x1;
+    parameter real FREQ = 100.0;
+endpackage
+
+package bus_cfg_pkg;
+    import cfg_pkg::*;
+    localparam int ADDR_W = DATA_W - 8;
+    localparam longint BEAT_W = cfg_pkg::DATA_W + STRB_W;
+endpackage
+
+module package_module
+    import cfg_pkg::DATA_W;
+#(
+    parameter int LANES = bus_cfg_pkg::ADDR_W / 8
+) (
+    input  logic [DATA_W-1:0]          data_i,
+    input  logic [cfg_pkg::STRB_W-1:0] strb_i,
+    input  logic [LANES-1:0]           lane_i
+);
+endmodule
@@ -0,0 +1,28 @@
+`timescale 1ns/1ps
+
+// Names that are reserved words or not identifiers in Scala
+`define val 3
+`define end$ 5
+
+package \pkg-a ;
+    parameter int def = 4;
+    parameter int w$ = 2;
+endpackage
+
+module object (
+    input  logic       val,
+    input  logic [3:0] \bus[0] ,
+    input  logic [3:0] bus_0_,
+    output logic       match,
+    output logic       \type 
+);
+
+    assign match = val;
+    assign \type = ^\bus[0] ;
+
+endmodule
+
+module \ip-core  (
+    input  logic       yield
+);
+endmodule
@@ -0,0 +1,31 @@
+package bus_pkg;
+    typedef logic [7:0] byte_t;
+
+    typedef struct packed {
+        byte_t      data;
+        logic [3:0] strb;
+        logic       last;
+    } beat_t;
+endpackage
+
+typedef enum logic [1:0] { IDLE, BUSY, DONE } state_t;
+typedef bus_pkg::beat_t beats_t [4];
+
+module sysfunc_module #(
+    parameter int unsigned LANES    = 4,
+    parameter logic [7:0]  MASK     = 8'b1011_0110,
+    parameter real         FREQ_MHZ = 250.0
+) (
+    input  logic [$bits(bus_pkg::beat_t)-1:0]   beat,
+    input  logic [$bits(beats_t)-1:0]           beats,
+    input  logic [$size(beats_t)-1:0]           beat_valid,
+    input  logic [$bits(state_t)-1:0]           state,
+    input  logic [$size(bus_pkg::byte_t)-1:0]   byte_lane,
+    input  logic [$high(MASK):$low(MASK)]       mask,
+    input  logic [$countones(MASK)-1:0]         ones,
+    input  logic [$rtoi(FREQ_MHZ / 100.0):0]    freq,
+    input  logic [$unsigned(-1) >> 28:0]        nibble,
+    output logic [$signed(4'b1000) < 0 ? 3 : 7 : 0] sign
+);
+
+endmodule
@@ -0,0 +1,5 @@
+// defs.svh must come first, unit_top.sv uses its defines
++define+LANES=2
+-timescale=1ns/1ps
+defs.svh
+unit_top.sv
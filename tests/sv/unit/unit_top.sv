@@ -0,0 +1,10 @@
+module unit_top #(
+    parameter LANES = `LANES
+) (
+    input  logic [`BUS_W-1:0] data_i,
+    input  logic [`BYTES-1:0] strb_i
+);
+endmodule
+
+`define BUS_W 64
+`undef HAS_PARITY
@@ -0,0 +1,13 @@
+`define W 8
+
+module unresolved_module #(parameter DW = EXT_W) (a, b, c, d, e, f, g);
+    input  [BUS_W-1:0] a;
+    output b;
+    input  [3:0] c;
+    input  [1'bx:0] d;
+    input  [2.5 % 2:0] e;
+    output [DW-1:0] f;
+    input  [`W*UNDEF_P-1:0] g;
+
+    reg [DEPTH:0] b;
+endmodule
@@ -0,0 +1,13 @@
+`define WIDE_MASK 80'hFFFF_FFFF_FFFF_FFFF_FFFF
+
+module wide_module #(
+    parameter SEED = 128'h1_0000_0000_0000_0000,
+    parameter WIDE_BITS = $clog2(SEED) + 1,
+    parameter UNKNOWN = 4'b10x1
+) (
+    input  logic [(`WIDE_MASK >> 76) : 0] mask_hi,
+    input  logic [WIDE_BITS-1:0]          seed,
+    output logic [UNKNOWN:0]              flags
+);
+
+endmodule